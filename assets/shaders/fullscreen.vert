@@ -0,0 +1,12 @@
+#version 450
+
+// Corners of the screen, in normalized device coordinates
+layout(location=0) in vec3 Vertex_Position;
+
+// Texture coordinates, from the top left corner
+layout(location=0) out vec2 v_Uv;
+
+void main() {
+    v_Uv = vec2(Vertex_Position.x * 0.5 + 0.5, 0.5 - Vertex_Position.y * 0.5);
+    gl_Position = vec4(Vertex_Position.xy, 0.0, 1.0);
+}
@@ -5,6 +5,10 @@
 
 layout(early_fragment_tests) in;
 layout(location=0) out vec4 f_color;
+// Light accumulated over frames, read back through GIHistory on the next frame
+layout(location=1) out vec4 f_history;
+// Motion of the surface since the previous frame, in normalized device coordinates
+layout(location=2) out vec4 f_velocity;
 layout(location=0) in vec3 vWorldPosition;
 struct PerspectiveProjection {
     float fov;
@@ -26,18 +30,85 @@ layout(set = 0, binding = 0) uniform Camera {
     mat4 transform;
 };
 
-struct PointLight {
-    vec4 Color;
-    vec4 pos;
+struct Light {
+    vec4 color; // rgb: color * intensity, a: range
+    vec4 position; // xyz: position, w: light kind
+    vec4 direction; // xyz: spot light direction or area light normal, w: 1.0 if casting shadows
+    vec4 params; // spot light: cos(inner angle), cos(outer angle). area light: half width, half height
+    vec4 tangent; // area light: xyz axis along the width
 };
+#define LIGHT_KIND_POINT 0.0
+#define LIGHT_KIND_SPOT 1.0
+#define LIGHT_KIND_AREA 2.0
 layout(set = 1, binding = 0) uniform texture2DArray TextureRepo;
 layout(set = 1, binding = 1) uniform sampler TextureRepoSampler;
-layout(set = 1, binding = 2) uniform Lights {
+layout(set = 1, binding = 2) readonly buffer Lights {
     vec4 AmbientLightColor;
     vec4 SunLightColor;
     vec3 SunLightDir;
-    uint PointLightCount;
-    PointLight lights[];
+    uint LightCount;
+    vec4 SkyZenithColor;
+    vec4 SkyHorizonColor;
+    vec4 SkyGroundColor;
+    Light lights[];
+};
+layout(set = 1, binding = 3) uniform RayTracerSettings {
+    uint SunShadowsEnabled;
+    uint SunShadowMaxIterations;
+    // sin() of the minimum angle between the sun and a lit surface
+    float SunMinGrazingFactor;
+    uint LightShadowsEnabled;
+    uint LightShadowMaxIterations;
+    float LightMinGrazingFactor;
+    uint AreaLightSamples;
+    uint FrameIndex;
+    uint AmbientOcclusionEnabled;
+    uint AmbientOcclusionSamples;
+    uint AmbientOcclusionMaxIterations;
+    float AmbientOcclusionDistance;
+    uint GIEnabled;
+    uint GIMaxIterations;
+    uint GIMaxAccumulatedFrames;
+    // Number of frames since the camera or the chunks last changed
+    uint AccumulatedFrames;
+    uint ReflectionMaxBounces;
+    uint ReflectionMaxIterations;
+    // Seconds since startup
+    float Time;
+    float _reserved;
+    // rgb: fog color, a: opacity of the fog. Transparent fog fades out the voxels instead.
+    vec4 FogColor;
+    uint FogMode;
+    // Linear: start, end. Exponential: density. Height: density, falloff, height
+    float FogParam0;
+    float FogParam1;
+    float FogParam2;
+    // ViewProj of the previous frame, for the motion of the surfaces
+    mat4 PreviousViewProj;
+    uint TaaEnabled;
+    float TaaHistoryWeight;
+    // Shows the octree node fetches of the primary rays instead of the shaded voxels
+    uint IterationHeatmap;
+};
+// Defined for the cameras without the history of the previous frames
+#ifndef WITHOUT_HISTORY
+layout(set = 1, binding = 4) uniform texture2D GIHistory;
+layout(set = 1, binding = 5) uniform sampler GIHistorySampler;
+#endif
+// Animation of every layer of TextureRepo. Animated textures are consecutive layers,
+// and only the first layer of an animation holds the frame count and the period.
+struct TextureFrame {
+    uint frame_count;
+    float period;
+    float frame_end; // Time since the start of the animation at which the frame ends
+    float _reserved;
+};
+layout(set = 1, binding = 6) readonly buffer TextureFrames {
+    TextureFrame textureFrames[];
+};
+// Inverse of the ViewProj of the camera, uploaded by its ray pass
+layout(set = 1, binding = 7) uniform InverseCamera {
+    mat4 InverseViewProj;
 };
 layout (constant_id = 0) const uint MAX_ITERATION_VALUE = 1000;
 
@@ -50,16 +121,33 @@ struct Material {
     float scale;
     uint16_t diffuse;
     uint16_t normal;
-    float _reserved1;
-    float _reserved2;
+    float reflectivity;
+    float ior; // 0 for opaque materials
+    float roughness;
+    uint transparency;
+    float alpha_cutoff;
+    float metallic;
+    vec4 emission;
+    uint16_t face_diffuse[6]; // +X, -X, +Y, -Y, +Z, -Z
+    uint16_t metallic_roughness;
+    uint16_t _reserved2;
 };
 struct ColoredMaterial {
     float scale;
     uint16_t diffuse;
     uint16_t normal;
-    float _reserved1;
-    float _reserved2;
+    float reflectivity;
+    float ior; // 0 for opaque materials
+    float roughness;
+    uint transparency;
+    float alpha_cutoff;
+    float metallic;
+    vec4 emission;
+    uint16_t face_diffuse[6]; // +X, -X, +Y, -Y, +Z, -Z
+    uint16_t metallic_roughness;
+    uint16_t _reserved2;
     vec4 palette[256];
+    float emission_palette[256];
 };
 layout(set = 2, binding = 1) readonly buffer ColoredMaterials {
     ColoredMaterial coloredMaterials[];
@@ -67,15 +155,62 @@ layout(set = 2, binding = 1) readonly buffer ColoredMaterials {
 layout(set = 2, binding = 2) readonly buffer Materials {
     Material regularMaterials[];
 };
+// Indices into `lights` of the lights that can reach this chunk
+layout(set = 2, binding = 3) readonly buffer ChunkLights {
+    uint ChunkLightCount;
+    uint ChunkLightIndices[];
+};
 struct Ray {
     vec3 origin;
     vec3 dir;
 };
 
+// Element of the Halton low discrepancy sequence, in 0..1
+float halton(uint index, uint base) {
+    float fraction = 1.0;
+    float result = 0.0;
+    while (index > 0) {
+        fraction /= float(base);
+        result += fraction * float(index % base);
+        index /= base;
+    }
+    return result;
+}
+
+// Sub-pixel offset of the rays of the current frame, cycling through 8 offsets so that
+// temporal anti-aliasing averages several rays per pixel. Cameras without history aren't
+// anti-aliased, and keep their rays at the center of the pixels.
+vec2 pixel_jitter() {
+    #ifdef WITHOUT_HISTORY
+    return vec2(0.0);
+    #else
+    if (TaaEnabled == 0) {
+        return vec2(0.0);
+    }
+    uint index = FrameIndex % 8 + 1;
+    return vec2(halton(index, 2), halton(index, 3)) - 0.5;
+    #endif
+}
+
+// World space position of a point in normalized device coordinates
+vec3 unproject(vec2 ndc, float depth) {
+    vec4 position = InverseViewProj * vec4(ndc, depth, 1.0);
+    return position.xyz / position.w;
+}
+
+// Ray from the near plane through the fragment. Perspective projections give rays coming
+// from the camera, and orthographic projections parallel rays.
 Ray generate_ray() {
+    // Moving along the screen space derivatives of the proxy cube surface shifts the ray
+    // by a fraction of a pixel
+    vec2 jitter = pixel_jitter();
+    vec3 target = vWorldPosition + dFdx(vWorldPosition) * jitter.x + dFdy(vWorldPosition) * jitter.y;
+    vec4 clip = ViewProj * vec4(target, 1.0);
+    vec2 ndc = clip.xy / clip.w;
+
     Ray ray;
-    ray.origin = transform[3].xyz;
-    ray.dir = normalize(vWorldPosition - ray.origin);
+    ray.origin = unproject(ndc, 0.0);
+    ray.dir = normalize(unproject(ndc, 0.5) - ray.origin);
     return ray;
 }
 vec2 intersectAABB(vec3 origin, vec3 dir, vec4 box) {
@@ -105,167 +240,951 @@ uint mask_location_nth_one(uint mask, uint location) {
     return bitCount(mask & ((1 << location) - 1));
 }
 
+// Deepest octree level the traversal stack can hold. A 512^3 chunk only needs 9.
+#define MAX_OCTREE_DEPTH 16
+// The traversal runs on an integer grid with GRID_SIZE cells along each axis.
+// A leaf at depth d covers GRID_SIZE >> d cells.
+#define GRID_SIZE (1 << MAX_OCTREE_DEPTH)
+#define T_MAX 3.402823466e+38
+#define PI 3.14159265359
+#define FOG_NONE 0
+#define FOG_LINEAR 1
+#define FOG_EXPONENTIAL 2
+#define FOG_EXPONENTIAL_SQUARED 3
+#define FOG_HEIGHT 4
+// Regular voxels hold a material id in bits 0 - 9 and an orientation in bits 10 - 14
+#define REGULAR_MATERIAL_MASK 0x3ff
+#define ORIENTATION_SHIFT 10
+#define TRANSPARENCY_OPAQUE 0
+#define TRANSPARENCY_BLEND 1
+#define TRANSPARENCY_MASK 2
+// Number of translucent surfaces and texture holes a primary ray may go through
+#define MAX_TRANSLUCENT_LAYERS 8
 
-uint material_at_position(inout vec4 box, vec3 position) {
-    uint node_index = 0; // Assume root node
+struct Hit {
+    uint voxel_id;
+    // Distance along the ray
+    float t;
+    vec3 position;
+    // Normal of the face the ray entered the voxel through.
+    // Zero when the ray started inside the voxel.
+    vec3 normal;
+    // Normal used for lighting, perturbed by the normal map of the material
+    vec3 shading_normal;
+    // The voxel hit, in world space
+    vec4 box;
+    // Min corner and size of the voxel on the integer grid
+    ivec3 cell;
+    int cell_size;
+    // Number of node fetches spent on the ray
+    uint iterations;
+    // The ray left the chunk without hitting anything
+    bool escaped;
+};
 
-    while(true) {
-        // start
-        // Calculate new box location
-        box.w = box.w / 2;
-        vec3 box_midpoint = box.xyz + box.w;
-        vec3 s = step(box_midpoint, position);
-        box.xyz = box.xyz + s * box.w;
+// Stack based octree traversal on integer cell coordinates.
+// The leaf containing a cell is located from the bits of its coordinates. The traversal
+// keeps the chain of parent nodes containing the current cell. After stepping into the
+// next cell, it pops the nodes below the highest bit that changed and descends again
+// from the deepest node that still contains it, so advancing to a sibling only costs a
+// single node fetch.
+// The ray moves from one leaf to the next through the face with the smallest exact t value,
+// so there are no epsilons that could make it skip thin walls.
+// `cell` is the first cell to visit, containing the point ray.origin + ray.dir * t_start.
+// Voxels made of the `medium` are traversed like air.
+Hit OctreeMarchCells(vec4 initial_box, Ray ray, float t_start, ivec3 cell, vec3 normal, uint medium, uint max_iterations) {
+    float scale = float(GRID_SIZE) / initial_box.w;
+    vec3 origin = (ray.origin + ray.dir * t_start - initial_box.xyz) * scale;
+    vec3 dir = ray.dir * scale;
+    float t = 0.0;
 
+    uint stack_node[MAX_OCTREE_DEPTH];
+    int depth = 0;
+    stack_node[0] = 0; // root node
 
-        uint child_index = uint(dot(s, vec3(4,2,1)));
-        uint freemask = uint(nodes[node_index].freemask);
-        if ((freemask & (1 << child_index)) == 0) {
-            // is a leaf node
-            return uint(nodes[node_index].data[child_index]);
-        } else {
+    Hit hit;
+    hit.voxel_id = 0;
+    hit.escaped = false;
+    ivec3 leaf_min = cell;
+    int leaf_size = 1;
+    uint counter = 0;
+    while (counter < max_iterations) {
+        if (any(lessThan(cell, ivec3(0))) || any(greaterThanEqual(cell, ivec3(GRID_SIZE)))) {
+            // Outside the box
+            hit.escaped = true;
+            break;
+        }
+        // Descend into the leaf containing the cell
+        uint voxel_id;
+        while (true) {
+            counter++;
+            uint node_index = stack_node[depth];
+            int shift = MAX_OCTREE_DEPTH - 1 - depth;
+            ivec3 s = (cell >> shift) & 1;
+            uint child_index = uint((s.x << 2) | (s.y << 1) | s.z);
+            uint freemask = uint(nodes[node_index].freemask);
+            if ((freemask & (1 << child_index)) == 0 || depth == MAX_OCTREE_DEPTH - 1) {
+                // is a leaf node
+                leaf_size = 1 << shift;
+                leaf_min = cell & ~(leaf_size - 1);
+                voxel_id = uint(nodes[node_index].data[child_index]);
+                break;
+            }
             // has children
             uint child_offset = mask_location_nth_one(freemask, child_index);
-            node_index = nodes[node_index].children + child_offset;
+            depth++;
+            stack_node[depth] = nodes[node_index].children + child_offset;
+        }
+        if (voxel_id != medium) {
+            // Hit some materials
+            hit.voxel_id = voxel_id;
+            break;
         }
+
+        // Step into the neighbor leaf through the face with the smallest t
+        ivec3 leaf_max = leaf_min + leaf_size - 1;
+        vec3 planes = vec3(mix(leaf_min, leaf_max + 1, greaterThan(dir, vec3(0.0))));
+        vec3 t_planes = mix((planes - origin) / dir, vec3(T_MAX), equal(dir, vec3(0.0)));
+        float t_exit = min(t_planes.x, min(t_planes.y, t_planes.z));
+        // Pick exactly one axis even when the ray leaves through an edge or a corner
+        bvec3 exit_axis = bvec3(t_exit == t_planes.x, false, false);
+        exit_axis.y = !exit_axis.x && t_exit == t_planes.y;
+        exit_axis.z = !exit_axis.x && !exit_axis.y;
+        t = max(t, t_exit);
+
+        // Cells on the other axes are kept within the current leaf, so the ray always
+        // moves into a leaf sharing the exit face.
+        ivec3 next = clamp(ivec3(floor(origin + dir * t)), leaf_min, leaf_max);
+        next = mix(next, mix(leaf_min - 1, leaf_max + 1, greaterThan(dir, vec3(0.0))), exit_axis);
+        normal = -sign(dir) * vec3(exit_axis);
+
+        // Pop the nodes that do not contain the next cell.
+        // Those are the ones below the highest bit that changed.
+        ivec3 changed = next ^ cell;
+        int changed_bit = findMSB(changed.x | changed.y | changed.z);
+        depth = max(0, min(depth, MAX_OCTREE_DEPTH - 1 - changed_bit));
+        cell = next;
     }
+    hit.t = t_start + t;
+    hit.position = ray.origin + ray.dir * hit.t;
+    hit.normal = normal;
+    hit.shading_normal = normal;
+    hit.cell = leaf_min;
+    hit.cell_size = leaf_size;
+    hit.box = vec4(initial_box.xyz + vec3(leaf_min) / scale, float(leaf_size) / scale);
+    hit.iterations = counter;
+    return hit;
 }
 
-vec3 cubed_normalize(vec3 dir) {
-    vec3 dir_abs = abs(dir);
-    float max_element = max(dir_abs.x, max(dir_abs.y, dir_abs.z));
-    return -sign(dir) * step(max_element, dir_abs);
+// Casts a ray from outside the chunk or from any point inside it.
+Hit OctreeMarch(vec4 initial_box, Ray ray, uint max_iterations) {
+    vec2 intersection = intersectAABB(ray.origin, ray.dir, initial_box);
+    if (intersection.x > intersection.y || intersection.y < 0) {
+        Hit hit;
+        hit.voxel_id = 0;
+        hit.escaped = true;
+        hit.iterations = 0;
+        return hit;
+    }
+    float t_start = max(0, intersection.x);
+    float scale = float(GRID_SIZE) / initial_box.w;
+    vec3 entry_point = (ray.origin + ray.dir * t_start - initial_box.xyz) * scale;
+
+    // Points on a cell boundary belong to the cell the ray is heading into
+    vec3 entry_floor = floor(entry_point);
+    ivec3 cell = ivec3(entry_floor) - ivec3(lessThan(ray.dir, vec3(0.0))) * ivec3(equal(entry_floor, entry_point));
+    cell = clamp(cell, ivec3(0), ivec3(GRID_SIZE - 1));
+
+    vec3 normal = vec3(0.0);
+    if (intersection.x > 0) {
+        vec3 t1 = min((initial_box.xyz - ray.origin) / ray.dir, (initial_box.xyz + initial_box.w - ray.origin) / ray.dir);
+        normal = -sign(ray.dir) * vec3(equal(t1, vec3(intersection.x)));
+    }
+    return OctreeMarchCells(initial_box, ray, t_start, cell, normal, 0, max_iterations);
 }
 
+// Casts a secondary ray entering the leaf of a previous hit, through voxels of the `medium`.
+// The ray stops at the first leaf made of anything else, air included when `medium` is not 0.
+Hit OctreeMarchInto(vec4 initial_box, Hit from, vec3 dir, uint medium, uint max_iterations) {
+    Ray ray;
+    ray.origin = from.position;
+    ray.dir = dir;
+    float scale = float(GRID_SIZE) / initial_box.w;
+    vec3 position = (from.position - initial_box.xyz) * scale;
+    ivec3 cell = clamp(ivec3(floor(position)), from.cell, from.cell + from.cell_size - 1);
+    return OctreeMarchCells(initial_box, ray, 0.0, cell, from.normal, medium, max_iterations);
+}
+
+// The point where a ray going through the leaf of a hit leaves it, as a hit on the exit face
+// with the normal pointing out of the leaf. Secondary rays cast from it continue past the leaf.
+Hit LeafExit(Hit from, vec3 dir) {
+    vec3 planes = mix(from.box.xyz, from.box.xyz + from.box.w, greaterThan(dir, vec3(0.0)));
+    vec3 t_planes = mix((planes - from.position) / dir, vec3(T_MAX), equal(dir, vec3(0.0)));
+    float t_exit = min(t_planes.x, min(t_planes.y, t_planes.z));
+    bvec3 exit_axis = bvec3(t_exit == t_planes.x, false, false);
+    exit_axis.y = !exit_axis.x && t_exit == t_planes.y;
+    exit_axis.z = !exit_axis.x && !exit_axis.y;
+    Hit exit = from;
+    exit.t = from.t + t_exit;
+    exit.position = from.position + dir * t_exit;
+    exit.normal = sign(dir) * vec3(exit_axis);
+    exit.shading_normal = exit.normal;
+    return exit;
+}
 
-uint RayMarch(vec4 initial_box, Ray ray, out vec3 hitpoint, out vec4 hitbox, out uint iteration_times) {
-    hitbox = initial_box;
-    vec2 intersection = intersectAABB(ray.origin, ray.dir, hitbox);
-    vec3 entry_point = ray.origin + max(0, intersection.x) * ray.dir;
-    vec3 test_point = entry_point + ray.dir * hitbox.w * 0.000001;
-    uint material_id = 0;
+// Casts a secondary ray leaving the face of a previous hit.
+// The ray starts in the cell in front of the face, so it never hits the voxel it starts from.
+Hit OctreeMarchFromHit(vec4 initial_box, Hit from, vec3 dir, uint max_iterations) {
+    Ray ray;
+    ray.origin = from.position;
+    ray.dir = dir;
+    float scale = float(GRID_SIZE) / initial_box.w;
+    vec3 position = (from.position - initial_box.xyz) * scale;
+    ivec3 leaf_max = from.cell + from.cell_size - 1;
+    ivec3 cell = clamp(ivec3(floor(position)), from.cell, leaf_max);
+    cell = mix(cell, mix(from.cell - 1, leaf_max + 1, greaterThan(from.normal, vec3(0.0))), notEqual(from.normal, vec3(0.0)));
+    return OctreeMarchCells(initial_box, ray, 0.0, cell, from.normal, 0, max_iterations);
+}
 
-    uint counter;
-    for(counter = 0;; counter++) {
-        vec4 entry_point_camera_space = ViewProj * vec4(entry_point, 1.0);
-        //gl_FragDepth = ((entry_point_camera_space.z/entry_point_camera_space.w) + 1.0) * 0.5;
-        if (counter >= MAX_ITERATION_VALUE) {
-            // Ray tracing failed
+struct Surface {
+    float reflectivity;
+    float ior;
+    float roughness;
+    float metallic;
+    uint metallic_roughness;
+    uint transparency;
+    float alpha_cutoff;
+};
+
+Surface surface_at(uint voxel_id) {
+    Surface surface;
+    if (voxel_id == 0) {
+        surface.reflectivity = 0.0;
+        surface.ior = 0.0;
+        surface.roughness = 0.0;
+        surface.metallic = 0.0;
+        surface.metallic_roughness = 0;
+        surface.transparency = TRANSPARENCY_OPAQUE;
+        surface.alpha_cutoff = 0.0;
+    } else if ((voxel_id & 0x8000) == 0) {
+        Material material = regularMaterials[(voxel_id & REGULAR_MATERIAL_MASK) - 1];
+        surface.reflectivity = material.reflectivity;
+        surface.ior = material.ior;
+        surface.roughness = material.roughness;
+        surface.metallic = material.metallic;
+        surface.metallic_roughness = uint(material.metallic_roughness);
+        surface.transparency = material.transparency;
+        surface.alpha_cutoff = material.alpha_cutoff;
+    } else {
+        uint material_id = (voxel_id >> 8) & 0x7f;
+        surface.reflectivity = coloredMaterials[material_id].reflectivity;
+        surface.ior = coloredMaterials[material_id].ior;
+        surface.roughness = coloredMaterials[material_id].roughness;
+        surface.metallic = coloredMaterials[material_id].metallic;
+        surface.metallic_roughness = uint(coloredMaterials[material_id].metallic_roughness);
+        surface.transparency = coloredMaterials[material_id].transparency;
+        surface.alpha_cutoff = coloredMaterials[material_id].alpha_cutoff;
+    }
+    return surface;
+}
+
+// Defined with the textures below
+vec4 albedo(Hit hit);
+
+// Casts a secondary ray leaving the face of a previous hit like `OctreeMarchFromHit`, going
+// through the holes of masked voxels and through translucent voxels, up to the first opaque
+// voxel or `max_distance`. `transmittance` is scaled by the fraction of light the translucent
+// voxels let through. Rays going through too many layers stop at the last one, as if it was opaque.
+Hit OctreeMarchOpaque(vec4 initial_box, Hit from, vec3 dir, float max_distance, uint max_iterations, inout float transmittance) {
+    Hit hit = OctreeMarchFromHit(initial_box, from, dir, max_iterations);
+    for (uint layer = 0; layer < MAX_TRANSLUCENT_LAYERS && hit.voxel_id != 0; layer++) {
+        Surface surface = surface_at(hit.voxel_id);
+        if (surface.transparency == TRANSPARENCY_OPAQUE || dot(hit.position - from.position, dir) >= max_distance) {
             break;
         }
-        if (!containsAABB(test_point, bounding_box)) {
-            // Outside the box
-            discard;
+        float alpha = albedo(hit).a;
+        if (surface.transparency == TRANSPARENCY_MASK) {
+            if (alpha >= surface.alpha_cutoff) {
+                break;
+            }
+            hit = OctreeMarchFromHit(initial_box, LeafExit(hit, dir), dir, max_iterations);
+        } else {
+            transmittance *= 1.0 - alpha;
+            if (transmittance <= 0.01) {
+                break;
+            }
+            Hit inner = OctreeMarchInto(initial_box, hit, dir, hit.voxel_id, max_iterations);
+            if (!inner.escaped && inner.voxel_id == 0) {
+                inner = OctreeMarchInto(initial_box, inner, dir, 0, max_iterations);
+            }
+            hit = inner;
         }
-        hitbox = initial_box;
-        material_id = material_at_position(hitbox, test_point);
-        if (material_id > 0) {
-            // Hit some materials
-            break;
+    }
+    // Each layer restarts the distance from where it was entered
+    hit.t = dot(hit.position - from.position, dir);
+    return hit;
+}
+
+// Fraction of the light coming along `dir` from `max_distance` away that reaches a hit.
+// 0 when an opaque voxel occludes it.
+float Transmittance(vec4 initial_box, Hit from, vec3 dir, float max_distance, uint max_iterations) {
+    float transmittance = 1.0;
+    Hit occluder = OctreeMarchOpaque(initial_box, from, dir, max_distance, max_iterations, transmittance);
+    if (occluder.voxel_id > 0 && occluder.t < max_distance) {
+        return 0.0;
+    }
+    return transmittance;
+}
+
+// Light coming from the sky, and the ambient light, in a direction
+vec3 environment_light(vec3 dir) {
+    float y = normalize(dir).y;
+    vec3 sky = y >= 0.0
+        ? mix(SkyHorizonColor.rgb, SkyZenithColor.rgb, sqrt(y))
+        : mix(SkyHorizonColor.rgb, SkyGroundColor.rgb, sqrt(-y));
+    return AmbientLightColor.rgb + sky;
+}
+
+// Light of the sky and the ambient light reaching a surface, ignoring occluders.
+// The sky is approximated by the average colors of its upper and lower halves.
+vec3 ambient_light(vec3 normal) {
+    vec3 upper = mix(SkyHorizonColor.rgb, SkyZenithColor.rgb, 0.5);
+    vec3 lower = mix(SkyHorizonColor.rgb, SkyGroundColor.rgb, 0.5);
+    return AmbientLightColor.rgb + mix(lower, upper, normal.y * 0.5 + 0.5);
+}
+
+// Inputs of the BRDF at the surface that was hit
+struct Pbr {
+    vec3 diffuse_color; // Base color scattered diffusely, black for metals
+    vec3 f0; // Reflectance at normal incidence, the base color for metals
+    vec3 reflection_tint; // Tint of mirror reflections, the base color for metals
+    float roughness; // Perceptual roughness
+    vec3 view_dir; // Toward the viewer
+};
+
+// Cook-Torrance BRDF with a GGX distribution, a height correlated Smith visibility and
+// Schlick's Fresnel, times the cosine of the light angle.
+// Scaled by PI so that a white diffuse surface facing the light reflects the light color.
+vec3 brdf(Pbr pbr, vec3 normal, vec3 light_dir) {
+    vec3 half_dir = normalize(light_dir + pbr.view_dir);
+    float n_dot_v = abs(dot(normal, pbr.view_dir)) + 1e-5;
+    float n_dot_l = clamp(dot(normal, light_dir), 0.0, 1.0);
+    float n_dot_h = clamp(dot(normal, half_dir), 0.0, 1.0);
+    float l_dot_h = clamp(dot(light_dir, half_dir), 0.0, 1.0);
+
+    float a = pbr.roughness * pbr.roughness;
+    float a2 = a * a;
+    float d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    float distribution = a2 / (PI * d * d);
+    float visibility = 0.5 / (
+        n_dot_l * sqrt(n_dot_v * n_dot_v * (1.0 - a2) + a2)
+        + n_dot_v * sqrt(n_dot_l * n_dot_l * (1.0 - a2) + a2)
+    );
+    vec3 fresnel = pbr.f0 + (1.0 - pbr.f0) * pow(1.0 - l_dot_h, 5.0);
+
+    return (pbr.diffuse_color + PI * distribution * visibility * fresnel) * n_dot_l;
+}
+
+// Fraction of a uniform ambient light reflected toward the viewer, with the analytic
+// approximation of the environment BRDF from "Physically Based Shading on Mobile" (Karis)
+vec3 ambient_brdf(Pbr pbr, vec3 normal) {
+    const vec4 c0 = vec4(-1.0, -0.0275, -0.572, 0.022);
+    const vec4 c1 = vec4(1.0, 0.0425, 1.04, -0.04);
+    vec4 r = pbr.roughness * c0 + c1;
+    float n_dot_v = clamp(dot(normal, pbr.view_dir), 0.0, 1.0);
+    float a004 = min(r.x * r.x, exp2(-9.28 * n_dot_v)) * r.x + r.y;
+    vec2 scale_bias = vec2(-1.04, 1.04) * a004 + r.zw;
+    return pbr.diffuse_color + pbr.f0 * scale_bias.x + scale_bias.y;
+}
+
+// Sunlight reflected toward the viewer by the surface that was hit
+vec3 sun_light(Hit hit, Pbr pbr) {
+    vec3 light_dir = -normalize(SunLightDir);
+    float sun_light_factor = dot(hit.shading_normal, light_dir);
+    // When the angle between the light and the surface is small, the contribution is close
+    // to zero while tracing the shadow ray in the octree costs the most.
+    if (sun_light_factor <= SunMinGrazingFactor) {
+        return vec3(0.0);
+    }
+    float shadow = 1.0;
+    if (SunShadowsEnabled != 0) {
+        shadow = Transmittance(bounding_box, hit, light_dir, T_MAX, SunShadowMaxIterations);
+        if (shadow <= 0.0) {
+            // Occluded
+            return vec3(0.0);
         }
-        // calculate the next t_min
-        vec2 new_intersection = intersectAABB(entry_point, ray.dir, hitbox);
-
-        entry_point = entry_point + ray.dir * new_intersection.y;
-        test_point = entry_point + sign(ray.dir) * hitbox.w * 0.0001;
-    }
-    hitpoint = entry_point;
-    iteration_times = counter;
-    return material_id;
-}
-
-// Return true if occluded
-bool RayMarchTest(vec4 initial_box, Ray ray) {
-    vec4 hitbox = initial_box;
-    vec2 intersection = intersectAABB(ray.origin, ray.dir, hitbox);
-    vec3 entry_point = ray.origin + max(0, intersection.x) * ray.dir;
-    vec3 test_point = entry_point + ray.dir * hitbox.w * 0.000001;
-    uint material_id = 0;
-
-    for(uint counter = 0; counter < 50 && containsAABB(test_point, bounding_box); counter++) {
-        hitbox = initial_box;
-        material_id = material_at_position(hitbox, test_point);
-        if (material_id > 0) {
-            // get the depth info from entry_point
-            return true;
+    }
+    return brdf(pbr, hit.shading_normal, light_dir) * SunLightColor.rgb * shadow;
+}
+
+// Inverse square falloff, windowed so that it reaches zero at the light range
+float distance_attenuation(float distance, float range) {
+    float factor = distance / range;
+    float window = clamp(1.0 - factor * factor * factor * factor, 0.0, 1.0);
+    return window * window / (distance * distance + 1.0);
+}
+
+// Hash based random number generator, see "Hash Functions for GPU Rendering" (Jarzynski & Olano)
+uint pcg_hash(uint state) {
+    state = state * 747796405u + 2891336453u;
+    uint word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+// Random number in 0..1
+float random(inout uint seed) {
+    seed = pcg_hash(seed);
+    return float(seed) / 4294967296.0;
+}
+
+// Seed of the random numbers of the current pixel, changing every frame
+uint pixel_seed() {
+    return pcg_hash(uint(gl_FragCoord.x) + pcg_hash(uint(gl_FragCoord.y) + pcg_hash(FrameIndex)));
+}
+
+// Random direction in the hemisphere around the normal, more likely close to the normal
+vec3 cosine_weighted_direction(vec3 normal, inout uint seed) {
+    float phi = 2.0 * PI * random(seed);
+    float r2 = random(seed);
+    float r = sqrt(r2);
+    vec3 tangent = normalize(cross(normal, abs(normal.x) > 0.5 ? vec3(0.0, 1.0, 0.0) : vec3(1.0, 0.0, 0.0)));
+    vec3 bitangent = cross(normal, tangent);
+    return normalize(tangent * cos(phi) * r + bitangent * sin(phi) * r + normal * sqrt(1.0 - r2));
+}
+
+// Fraction of short rays around the surface that escape without hitting a nearby voxel.
+// 1.0 for open surfaces, lower in corners and caves.
+float ambient_occlusion(Hit hit, inout uint seed) {
+    if (AmbientOcclusionEnabled == 0 || AmbientOcclusionSamples == 0) {
+        return 1.0;
+    }
+    float occlusion = 0.0;
+    for (uint i = 0; i < AmbientOcclusionSamples; i++) {
+        vec3 dir = cosine_weighted_direction(hit.normal, seed);
+        float transmittance = Transmittance(bounding_box, hit, dir, AmbientOcclusionDistance, AmbientOcclusionMaxIterations);
+        occlusion += 1.0 - transmittance;
+    }
+    return 1.0 - occlusion / float(AmbientOcclusionSamples);
+}
+
+// Light coming from a point reflected toward the viewer, with shadows if enabled
+vec3 light_from_point(Hit hit, Pbr pbr, vec3 point, vec3 radiance, float range, bool shadows) {
+    vec3 to_light = point - hit.position;
+    float distance = length(to_light);
+    if (distance >= range) {
+        // Out of range
+        return vec3(0.0);
+    }
+    vec3 light_dir = to_light / distance;
+    float light_factor = dot(hit.shading_normal, light_dir);
+    if (light_factor <= LightMinGrazingFactor) {
+        return vec3(0.0);
+    }
+    float shadow = 1.0;
+    if (LightShadowsEnabled != 0 && shadows) {
+        shadow = Transmittance(bounding_box, hit, light_dir, distance, LightShadowMaxIterations);
+        if (shadow <= 0.0) {
+            // Occluded
+            return vec3(0.0);
         }
-        // calculate the next t_min
-        vec2 new_intersection = intersectAABB(test_point, ray.dir, hitbox);
+    }
+    return brdf(pbr, hit.shading_normal, light_dir) * distance_attenuation(distance, range) * radiance * shadow;
+}
 
-        entry_point = test_point + ray.dir * new_intersection.y;
-        test_point = entry_point + sign(ray.dir) * hitbox.w * 0.0001;
+// Light of a rectangular area light, estimated with random points on the rectangle
+// so that shadows get soft edges.
+vec3 area_light(Hit hit, Pbr pbr, Light light, inout uint seed) {
+    vec3 normal = light.direction.xyz;
+    if (dot(hit.position - light.position.xyz, normal) <= 0.0) {
+        // Behind the light
+        return vec3(0.0);
+    }
+    vec3 tangent = light.tangent.xyz;
+    vec3 bitangent = cross(normal, tangent);
+    uint samples = max(AreaLightSamples, 1);
+    vec3 light_color = vec3(0.0);
+    for (uint i = 0; i < samples; i++) {
+        vec2 offset = vec2(random(seed), random(seed)) * 2.0 - 1.0;
+        vec3 point = light.position.xyz
+            + tangent * offset.x * light.params.x
+            + bitangent * offset.y * light.params.y;
+        // The light emits less toward grazing directions
+        float emitter_factor = max(dot(normalize(hit.position - point), normal), 0.0);
+        light_color += emitter_factor * light_from_point(
+            hit, pbr, point, light.color.rgb, light.color.a, light.direction.w > 0.0);
     }
-    return false;
+    return light_color / float(samples);
 }
-void main() {
-    Ray ray = generate_ray();
 
-    float depth;
-    vec3 hitpoint;
-    vec4 hitbox;
-    uint iteration_times;
-    uint voxel_id = RayMarch(bounding_box, ray, hitpoint, hitbox, iteration_times);
-    float iteration = float(iteration_times) / float(MAX_ITERATION_VALUE); // 0 to 1
+// Light of all point, spot and area lights reaching the chunk that was hit,
+// reflected toward the viewer
+vec3 local_lights(Hit hit, Pbr pbr, inout uint seed) {
+    vec3 light_color = vec3(0.0);
+    for (uint i = 0; i < ChunkLightCount; i++) {
+        Light light = lights[ChunkLightIndices[i]];
+        bool shadows = light.direction.w > 0.0;
+        if (light.position.w == LIGHT_KIND_POINT) {
+            light_color += light_from_point(hit, pbr, light.position.xyz, light.color.rgb, light.color.a, shadows);
+        } else if (light.position.w == LIGHT_KIND_SPOT) {
+            vec3 to_hit = normalize(hit.position - light.position.xyz);
+            float cone_factor = smoothstep(light.params.y, light.params.x, dot(to_hit, light.direction.xyz));
+            if (cone_factor <= 0.0) {
+                continue;
+            }
+            light_color += cone_factor * light_from_point(hit, pbr, light.position.xyz, light.color.rgb, light.color.a, shadows);
+        } else {
+            light_color += area_light(hit, pbr, light, seed);
+        }
+    }
+    return light_color;
+}
 
-    #ifdef MATERIAL_DEBUG
-    f_color = vec4(iteration, iteration, iteration, 1.0);
-    #else
+// Maps 0..1 to blue - green - yellow - red for visualizing traversal costs
+vec3 heatmap(float value) {
+    value = clamp(value, 0.0, 1.0);
+    return clamp(vec3(
+        value * 4.0 - 2.0,
+        2.0 - abs(value * 4.0 - 2.0),
+        2.0 - value * 4.0
+    ), 0.0, 1.0);
+}
 
-    vec3 normal = cubed_normalize(hitpoint - (hitbox.xyz + hitbox.w/2));
-    vec2 texcoords = vec2(
+// Texture coordinates on the face that was hit
+vec2 face_texcoords(Hit hit) {
+    vec3 hitpoint = hit.position;
+    vec3 normal = hit.normal;
+    return vec2(
         dot(vec3(hitpoint.z, hitpoint.x, -hitpoint.x), normal),
         dot(-sign(normal) * vec3(hitpoint.y, hitpoint.z, hitpoint.y), normal)
     );
+}
+
+// Index of the face with the given normal: +X, -X, +Y, -Y, +Z, -Z.
+// 6 when the normal is zero, for rays starting inside a voxel.
+uint face_index(vec3 normal) {
+    if (normal.x != 0.0) {
+        return normal.x > 0.0 ? 0 : 1;
+    } else if (normal.y != 0.0) {
+        return normal.y > 0.0 ? 2 : 3;
+    } else if (normal.z != 0.0) {
+        return normal.z > 0.0 ? 4 : 5;
+    }
+    return 6;
+}
+
+// Normal of the face with the given index
+vec3 face_normal(uint face) {
+    vec3 normal = vec3(0.0);
+    normal[face / 2] = (face & 1) == 0 ? 1.0 : -1.0;
+    return normal;
+}
+
+// Rotation of a voxel by its orientation, mirroring `Orientation` in orientation.rs.
+// The columns are the images of the X, Y and Z axes of the voxel.
+mat3 voxel_rotation(uint voxel_id) {
+    if ((voxel_id & 0x8000) != 0) {
+        // Colored voxels have no orientation
+        return mat3(1.0);
+    }
+    uint orientation = (voxel_id >> ORIENTATION_SHIFT) & 0x1f;
+    vec3 up = face_normal(((orientation >> 2) + 2) % 6);
+    vec3 forward = up.z != 0.0 ? vec3(0.0, -1.0, 0.0) : vec3(0.0, 0.0, -1.0);
+    for (uint i = 0; i < (orientation & 3); i++) {
+        forward = cross(up, forward);
+    }
+    return mat3(cross(up, -forward), up, -forward);
+}
+
+// The hit in the frame of its voxel, undoing the rotation around the center of the voxel
+Hit unrotated_hit(Hit hit, mat3 rotation) {
+    vec3 center = hit.box.xyz + hit.box.w * 0.5;
+    hit.position = transpose(rotation) * (hit.position - center) + center;
+    hit.normal = transpose(rotation) * hit.normal;
+    return hit;
+}
+
+// Layer of TextureRepo showing a texture at the current time.
+// Animated textures go through the layers of their frames.
+float texture_layer(uint texture_id) {
+    uint layer = texture_id - 1;
+    TextureFrame animation = textureFrames[layer];
+    // A zero period would leave mod undefined, stay on the first frame instead
+    if (animation.frame_count > 1 && animation.period > 0.0) {
+        float t = mod(Time, animation.period);
+        uint frame = 0;
+        while (frame + 1 < animation.frame_count && t >= textureFrames[layer + frame].frame_end) {
+            frame++;
+        }
+        layer += frame;
+    }
+    return float(layer);
+}
+
+// Color of the surface that was hit
+// The texture repo stores texels as they are in the files. Color textures are sRGB encoded,
+// while normal and metallic-roughness maps hold linear data.
+vec3 srgb_to_linear(vec3 srgb) {
+    return mix(
+        srgb / 12.92,
+        pow((srgb + 0.055) / 1.055, vec3(2.4)),
+        greaterThan(srgb, vec3(0.04045))
+    );
+}
+
+vec4 albedo(Hit hit) {
+    uint voxel_id = hit.voxel_id;
+    // Textures and faces are picked in the frame of the voxel
+    Hit local = unrotated_hit(hit, voxel_rotation(voxel_id));
+    vec2 texcoords = face_texcoords(local);
+    uint face = face_index(local.normal);
     vec4 output_color;
     uint diffuse_texture_id;
     float scale;
 
-    // Calculate ambient
-    // vec3 light_color = AmbientLightColor.rgb;
-
-    // Test Sunlight
-    // Ray light_ray;
-    // light_ray.dir = -SunLightDir;
-    // light_ray.origin = hitpoint + sign(light_ray.dir) * hitbox.w * 0.01;
-    // float sun_light_factor = max(0.0, dot(normal, SunLightDir));
-    // if (sun_light_factor > 0.05) {
-    //     // so that the angle between the light and the surface is not too small
-    //     // when the angle is small, ray tracing it in the octree costs more
-    //     if (!RayMarchTest(bounding_box, light_ray)) {
-    //         // Not occluded
-    //         // Add Sunlight
-    //         light_color += sun_light_factor * SunLightColor.rgb;
-    //     }
-    // }
-
-
     if (voxel_id == 0) {
         output_color = vec4(1.0, 1.0, 1.0, 1.0);
         diffuse_texture_id = 0;
     } else if ((voxel_id & 0x8000) == 0) {
         // regular
-        uint material_id = voxel_id - 1;
-        diffuse_texture_id = uint(regularMaterials[material_id].diffuse);
+        uint material_id = (voxel_id & REGULAR_MATERIAL_MASK) - 1;
+        diffuse_texture_id = face < 6
+            ? uint(regularMaterials[material_id].face_diffuse[face])
+            : uint(regularMaterials[material_id].diffuse);
         output_color = vec4(1.0, 1.0, 1.0, 1.0);
         scale = regularMaterials[material_id].scale;
     } else {
         // colored
         uint material_id = (voxel_id >> 8) & 0x7f;
         uint color = voxel_id & 0xff;
-        diffuse_texture_id = uint(coloredMaterials[material_id].diffuse);
+        diffuse_texture_id = face < 6
+            ? uint(coloredMaterials[material_id].face_diffuse[face])
+            : uint(coloredMaterials[material_id].diffuse);
         output_color = coloredMaterials[material_id].palette[color];
         scale = coloredMaterials[material_id].scale;
     }
 
     if (diffuse_texture_id > 0) {
-        output_color *= texture(
+        // The texture repo has no mipmaps. An explicit LOD also keeps this usable for
+        // secondary rays, where implicit derivatives are undefined.
+        vec4 texel = textureLod(
+            sampler2DArray(TextureRepo,  TextureRepoSampler),
+            vec3(texcoords * scale, texture_layer(diffuse_texture_id)),
+            0.0
+        );
+        output_color *= vec4(srgb_to_linear(texel.rgb), texel.a);
+    }
+    return output_color;
+}
+
+// Perturbs the shading normal of a hit by the normal map of its material.
+// The tangent frame follows the face texture coordinates: the tangent points toward
+// increasing u, and the bitangent up the texture, toward decreasing v.
+void apply_normal_map(inout Hit hit) {
+    uint voxel_id = hit.voxel_id;
+    uint normal_texture_id;
+    float scale;
+    if (voxel_id == 0) {
+        return;
+    } else if ((voxel_id & 0x8000) == 0) {
+        uint material_id = (voxel_id & REGULAR_MATERIAL_MASK) - 1;
+        normal_texture_id = uint(regularMaterials[material_id].normal);
+        scale = regularMaterials[material_id].scale;
+    } else {
+        uint material_id = (voxel_id >> 8) & 0x7f;
+        normal_texture_id = uint(coloredMaterials[material_id].normal);
+        scale = coloredMaterials[material_id].scale;
+    }
+    if (normal_texture_id == 0) {
+        return;
+    }
+    mat3 rotation = voxel_rotation(voxel_id);
+    Hit local = unrotated_hit(hit, rotation);
+    vec3 n = local.normal;
+    vec3 tangent = vec3(n.y - n.z, 0.0, n.x);
+    vec3 bitangent = vec3(0.0, abs(n.x) + abs(n.z), abs(n.y));
+    vec3 texel = textureLod(
+        sampler2DArray(TextureRepo,  TextureRepoSampler),
+        vec3(face_texcoords(local) * scale, texture_layer(normal_texture_id)),
+        0.0
+    ).xyz * 2.0 - 1.0;
+    hit.shading_normal = normalize(rotation * (tangent * texel.x + bitangent * texel.y + n * texel.z));
+}
+
+// Light emitted by the surface that was hit
+vec3 emission(Hit hit) {
+    uint voxel_id = hit.voxel_id;
+    if (voxel_id == 0) {
+        return vec3(0.0);
+    } else if ((voxel_id & 0x8000) == 0) {
+        return regularMaterials[(voxel_id & REGULAR_MATERIAL_MASK) - 1].emission.rgb;
+    } else {
+        uint material_id = (voxel_id >> 8) & 0x7f;
+        uint color = voxel_id & 0xff;
+        return coloredMaterials[material_id].emission.rgb
+            + coloredMaterials[material_id].palette[color].rgb * coloredMaterials[material_id].emission_palette[color];
+    }
+}
+
+// Inputs of the BRDF at a hit seen from view_dir. The palette color and diffuse texture
+// give the base color, and the metallic-roughness texture scales the material values.
+Pbr pbr_at(Hit hit, vec3 view_dir) {
+    Surface surface = surface_at(hit.voxel_id);
+    float roughness = surface.roughness;
+    float metallic = surface.metallic;
+    if (surface.metallic_roughness > 0) {
+        uint voxel_id = hit.voxel_id;
+        float scale = (voxel_id & 0x8000) == 0
+            ? regularMaterials[(voxel_id & REGULAR_MATERIAL_MASK) - 1].scale
+            : coloredMaterials[(voxel_id >> 8) & 0x7f].scale;
+        Hit local = unrotated_hit(hit, voxel_rotation(voxel_id));
+        vec4 texel = textureLod(
             sampler2DArray(TextureRepo,  TextureRepoSampler),
-            vec3(texcoords * scale, diffuse_texture_id-1)
+            vec3(face_texcoords(local) * scale, texture_layer(surface.metallic_roughness)),
+            0.0
         );
+        roughness *= texel.g;
+        metallic *= texel.b;
+    }
+    vec3 base_color = albedo(hit).rgb;
+    Pbr pbr;
+    pbr.diffuse_color = base_color * (1.0 - metallic);
+    pbr.f0 = mix(vec3(0.04), base_color, metallic);
+    pbr.reflection_tint = mix(vec3(1.0), base_color, metallic);
+    // Keeps the highlights of smooth surfaces from vanishing into single pixels
+    pbr.roughness = clamp(roughness, 0.045, 1.0);
+    pbr.view_dir = view_dir;
+    return pbr;
+}
+
+// One bounce of indirect light, estimated with a single cosine weighted ray.
+// Rays escaping the chunk bring the sky and ambient light, so this also occludes them.
+// Translucent voxels dim the light going through them.
+vec3 indirect_light(Hit hit, inout uint seed) {
+    vec3 dir = cosine_weighted_direction(hit.normal, seed);
+    float transmittance = 1.0;
+    Hit bounce = OctreeMarchOpaque(bounding_box, hit, dir, T_MAX, GIMaxIterations, transmittance);
+    if (bounce.voxel_id == 0) {
+        return environment_light(dir) * transmittance;
     }
+    // Emissive voxels light their surroundings through the bounce rays
+    Pbr pbr = pbr_at(bounce, -dir);
+    return (ambient_brdf(pbr, bounce.normal) * ambient_light(bounce.normal)
+        + sun_light(bounce, pbr) + emission(bounce)) * transmittance;
+}
+
+// Fraction of the light a surface scatters diffusely. The rest is reflected or refracted.
+float diffuse_factor(Surface surface) {
+    return surface.ior > 0.0 ? 0.0 : 1.0 - surface.reflectivity;
+}
+
+// Schlick's approximation of the fraction of light reflected by a transparent surface
+float fresnel(vec3 dir, vec3 normal, float ior) {
+    float r0 = (1.0 - ior) / (1.0 + ior);
+    r0 *= r0;
+    float cos_theta = clamp(-dot(dir, normal), 0.0, 1.0);
+    return r0 + (1.0 - r0) * pow(1.0 - cos_theta, 5.0);
+}
+
+// Mirror reflection, scattered around the mirror direction by the roughness
+vec3 rough_reflect(vec3 dir, Hit hit, float roughness, inout uint seed) {
+    vec3 reflected = reflect(dir, hit.shading_normal);
+    if (dot(reflected, hit.normal) <= 0.0) {
+        // Normal maps may bend the reflection into the surface
+        reflected = reflect(dir, hit.normal);
+    }
+    if (roughness <= 0.0) {
+        return reflected;
+    }
+    return normalize(mix(reflected, cosine_weighted_direction(hit.normal, seed), roughness * roughness));
+}
+
+// Lit color of a surface hit by a secondary ray, seen from view_dir
+vec3 shade(Hit hit, vec3 view_dir, inout uint seed) {
+    apply_normal_map(hit);
+    Pbr pbr = pbr_at(hit, view_dir);
+    return ambient_brdf(pbr, hit.shading_normal) * ambient_light(hit.shading_normal)
+        + sun_light(hit, pbr) + local_lights(hit, pbr, seed) + emission(hit);
+}
+
+// Color seen in the reflections and refractions of a reflective or transparent surface.
+// A single path is followed, choosing between reflection and refraction at random by the
+// Fresnel term, up to the first diffuse surface or ReflectionMaxBounces surfaces.
+vec3 specular_color(Hit hit, vec3 dir, inout uint seed) {
+    vec3 color = vec3(0.0);
+    vec3 throughput = vec3(1.0);
+    for (uint bounce = 0; bounce < ReflectionMaxBounces; bounce++) {
+        Surface surface = surface_at(hit.voxel_id);
+        Hit next;
+        if (surface.ior > 0.0 && random(seed) >= max(surface.reflectivity, fresnel(dir, hit.normal, surface.ior))) {
+            // Refract into the voxel, through the voxels of the same material and out again.
+            // The albedo of transparent materials tints the light going through them.
+            throughput *= albedo(hit).rgb;
+            dir = refract(dir, hit.normal, 1.0 / surface.ior);
+            next = OctreeMarchInto(bounding_box, hit, dir, hit.voxel_id, ReflectionMaxIterations);
+            if (!next.escaped && next.voxel_id == 0) {
+                vec3 exit_dir = refract(dir, next.normal, surface.ior);
+                // Total internal reflection is approximated by leaving unbent
+                if (exit_dir != vec3(0.0)) {
+                    dir = exit_dir;
+                }
+                next = OctreeMarchInto(bounding_box, next, dir, 0, ReflectionMaxIterations);
+            }
+        } else {
+            dir = rough_reflect(dir, hit, surface.roughness, seed);
+            next = OctreeMarchFromHit(bounding_box, hit, dir, ReflectionMaxIterations);
+        }
+        if (next.voxel_id == 0) {
+            // Left the chunk, or ran out of iterations
+            return color + throughput * environment_light(dir);
+        }
+        hit = next;
+        float diffuse = diffuse_factor(surface_at(hit.voxel_id));
+        color += throughput * diffuse * shade(hit, -dir, seed);
+        throughput *= 1.0 - diffuse;
+        if (diffuse >= 1.0) {
+            break;
+        }
+    }
+    return color;
+}
+
+// Fraction of the light of a surface at `distance` along the ray hidden by the fog
+float fog_factor(Ray ray, float distance) {
+    if (FogMode == FOG_LINEAR) {
+        return clamp((distance - FogParam0) / (FogParam1 - FogParam0), 0.0, 1.0);
+    } else if (FogMode == FOG_EXPONENTIAL) {
+        return 1.0 - exp(-FogParam0 * distance);
+    } else if (FogMode == FOG_EXPONENTIAL_SQUARED) {
+        float optical_depth = FogParam0 * distance;
+        return 1.0 - exp(-optical_depth * optical_depth);
+    } else if (FogMode == FOG_HEIGHT) {
+        // Integral of the density along the ray, the density falling off exponentially with height
+        float density = FogParam0 * exp(-FogParam1 * (ray.origin.y - FogParam2));
+        float falloff = FogParam1 * ray.dir.y * distance;
+        float optical_depth = density * distance
+            * (abs(falloff) > 1e-4 ? (1.0 - exp(-falloff)) / falloff : 1.0);
+        return 1.0 - exp(-optical_depth);
+    }
+    return 0.0;
+}
+
+// A surface of color `color` at `distance` along the ray, covered by the fog, with
+// premultiplied alpha. The fog covers the surface with its color by its opacity, and lets
+// whatever is behind the chunk show through for the rest.
+vec4 fogged(Ray ray, float distance, vec3 color) {
+    float fog = fog_factor(ray, distance);
+    return vec4((1.0 - fog) * color + fog * FogColor.a * FogColor.rgb, 1.0 - fog * (1.0 - FogColor.a));
+}
+
+// Casts a primary ray, going through the holes of masked voxels and blending the color of
+// translucent voxels into `translucent`, with premultiplied alpha, up to the first opaque voxel.
+// Each translucent voxel is covered by the fog at its own distance.
+Hit RayMarch(vec4 initial_box, Ray ray, inout vec4 translucent, inout uint seed) {
+    Hit hit = OctreeMarch(initial_box, ray, MAX_ITERATION_VALUE);
+    uint iterations = hit.iterations;
+    for (uint layer = 0; layer < MAX_TRANSLUCENT_LAYERS && hit.voxel_id != 0; layer++) {
+        Surface surface = surface_at(hit.voxel_id);
+        if (surface.transparency == TRANSPARENCY_OPAQUE) {
+            break;
+        }
+        float alpha = albedo(hit).a;
+        if (surface.transparency == TRANSPARENCY_MASK) {
+            if (alpha >= surface.alpha_cutoff) {
+                break;
+            }
+            // Through the hole, and on to the next leaf
+            hit = OctreeMarchFromHit(initial_box, LeafExit(hit, ray.dir), ray.dir, MAX_ITERATION_VALUE);
+        } else {
+            vec3 color = shade(hit, -ray.dir, seed);
+            translucent += (1.0 - translucent.a) * alpha * fogged(ray, distance(hit.position, ray.origin), color);
+            if (translucent.a >= 0.99) {
+                break;
+            }
+            // Through the voxels of the same material, and on to the next one
+            Hit inner = OctreeMarchInto(initial_box, hit, ray.dir, hit.voxel_id, MAX_ITERATION_VALUE);
+            if (!inner.escaped && inner.voxel_id == 0) {
+                iterations += inner.iterations;
+                inner = OctreeMarchInto(initial_box, inner, ray.dir, 0, MAX_ITERATION_VALUE);
+            }
+            hit = inner;
+        }
+        iterations += hit.iterations;
+    }
+    if (hit.escaped && translucent.a == 0.0) {
+        discard;
+    }
+    hit.iterations = iterations;
+    return hit;
+}
 
+void main() {
+    Ray ray = generate_ray();
+
+    uint seed = pixel_seed();
+    vec4 translucent = vec4(0.0);
+    Hit hit = RayMarch(bounding_box, ray, translucent, seed);
+    apply_normal_map(hit);
+    float iteration = float(hit.iterations) / float(MAX_ITERATION_VALUE); // 0 to 1
 
-    float ray_fog_factor = exp2(iteration * 18 - 18); // 0 for near, 1 for far
-    f_color = output_color * (1 - ray_fog_factor);
+    if (IterationHeatmap != 0) {
+        f_color = vec4(heatmap(iteration), 1.0);
+        f_history = vec4(0.0);
+        f_velocity = vec4(0.0);
+        return;
+    }
+
+    if (hit.escaped) {
+        // Only translucent voxels were hit, already covered by the fog.
+        // Blend them over whatever is behind the chunk.
+        f_color = vec4(translucent.rgb / translucent.a, translucent.a);
+        f_history = vec4(0.0);
+        f_velocity = vec4(0.0);
+        return;
+    }
+    vec3 ambient_color;
+    if (GIEnabled != 0) {
+        ambient_color = indirect_light(hit, seed);
+    } else {
+        ambient_color = ambient_light(hit.shading_normal) * ambient_occlusion(hit, seed);
+    }
+
+    // Average with the light of the previous frames, stored with the number of frames
+    // accumulated in alpha. AccumulatedFrames is reset to 0 when the scene changes.
+    // Only the light reaching the surface is accumulated, before it is scattered by the
+    // material, so that animated textures and view dependent reflections stay sharp.
+    #ifdef WITHOUT_HISTORY
+    f_history = vec4(ambient_color, 1.0);
+    #else
+    vec4 history = texelFetch(sampler2D(GIHistory, GIHistorySampler), ivec2(gl_FragCoord.xy), 0);
+    float frames = min(history.a, float(min(AccumulatedFrames, GIMaxAccumulatedFrames)));
+    ambient_color = (history.rgb * frames + ambient_color) / (frames + 1.0);
+    f_history = vec4(ambient_color, frames + 1.0);
+    #endif
+
+    Pbr pbr = pbr_at(hit, -ray.dir);
+    vec3 light_color = ambient_brdf(pbr, hit.shading_normal) * ambient_color
+        + sun_light(hit, pbr) + local_lights(hit, pbr, seed);
+
+    vec4 output_color = vec4(light_color, 1.0);
+
+    float diffuse = diffuse_factor(surface_at(hit.voxel_id));
+    if (diffuse < 1.0 && ReflectionMaxBounces > 0) {
+        vec3 reflection = specular_color(hit, ray.dir, seed) * pbr.reflection_tint;
+        output_color.rgb = output_color.rgb * diffuse + reflection * (1.0 - diffuse);
+    }
+
+    output_color.rgb += emission(hit);
+    output_color = fogged(ray, distance(hit.position, ray.origin), output_color.rgb);
+    output_color = translucent + output_color * (1.0 - translucent.a);
+    output_color.rgb = output_color.a > 0.0 ? output_color.rgb / output_color.a : vec3(0.0);
+    f_color = output_color;
+
+    #ifdef WITHOUT_HISTORY
+    // PreviousViewProj is the one of the 3d camera
+    f_velocity = vec4(0.0);
+    #else
+    vec4 clip = ViewProj * vec4(hit.position, 1.0);
+    vec4 previous_clip = PreviousViewProj * vec4(hit.position, 1.0);
+    f_velocity = vec4(clip.xy / clip.w - previous_clip.xy / previous_clip.w, 0.0, 0.0);
     #endif
 }
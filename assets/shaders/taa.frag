@@ -0,0 +1,56 @@
+#version 450
+
+// Anti-aliased color, read by the tone mapping pass
+layout(location=0) out vec4 f_color;
+// The same color, read back as TaaHistory on the next frame
+layout(location=1) out vec4 f_history;
+
+// Output of the ray pass, with premultiplied alpha
+layout(set = 0, binding = 0) uniform texture2D RayColor;
+layout(set = 0, binding = 1) uniform sampler RayColorSampler;
+layout(set = 0, binding = 2) uniform texture2D Velocity;
+layout(set = 0, binding = 3) uniform sampler VelocitySampler;
+layout(set = 0, binding = 4) uniform texture2D TaaHistory;
+layout(set = 0, binding = 5) uniform sampler TaaHistorySampler;
+// The temporal anti-aliasing part of the settings declared in ray.frag,
+// at TAA_SETTINGS_OFFSET
+layout(set = 0, binding = 6) uniform RayTracerSettings {
+    layout(offset = 176) uint TaaEnabled;
+    float TaaHistoryWeight;
+};
+
+void main() {
+    ivec2 size = textureSize(sampler2D(RayColor, RayColorSampler), 0);
+    ivec2 pixel = ivec2(gl_FragCoord.xy);
+    vec4 current = texelFetch(sampler2D(RayColor, RayColorSampler), pixel, 0);
+    if (TaaEnabled == 0) {
+        f_color = current;
+        f_history = current;
+        return;
+    }
+
+    // Range of the colors around the pixel. Clamping the history to it keeps surfaces that
+    // were uncovered or have changed from ghosting.
+    vec4 low = current;
+    vec4 high = current;
+    for (int y = -1; y <= 1; y++) {
+        for (int x = -1; x <= 1; x++) {
+            ivec2 neighbor_pixel = clamp(pixel + ivec2(x, y), ivec2(0), size - 1);
+            vec4 neighbor = texelFetch(sampler2D(RayColor, RayColorSampler), neighbor_pixel, 0);
+            low = min(low, neighbor);
+            high = max(high, neighbor);
+        }
+    }
+
+    // Where the surface was in the previous frame.
+    // Normalized device coordinates point up while texture coordinates point down.
+    vec2 velocity = texelFetch(sampler2D(Velocity, VelocitySampler), pixel, 0).xy;
+    vec2 previous_uv = gl_FragCoord.xy / vec2(size) - velocity * vec2(0.5, -0.5);
+    vec4 resolved = current;
+    if (all(greaterThanEqual(previous_uv, vec2(0.0))) && all(lessThanEqual(previous_uv, vec2(1.0)))) {
+        vec4 history = texture(sampler2D(TaaHistory, TaaHistorySampler), previous_uv);
+        resolved = mix(current, clamp(history, low, high), TaaHistoryWeight);
+    }
+    f_color = resolved;
+    f_history = resolved;
+}
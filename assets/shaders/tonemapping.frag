@@ -0,0 +1,44 @@
+#version 450
+
+layout(location=0) in vec2 v_Uv;
+
+layout(location=0) out vec4 f_color;
+
+// Output of the ray pass, with premultiplied alpha
+layout(set = 0, binding = 0) uniform texture2D HdrTexture;
+layout(set = 0, binding = 1) uniform sampler HdrTextureSampler;
+layout(set = 0, binding = 2) uniform Tonemapping {
+    uint TonemappingOperator;
+    float Exposure;
+};
+
+#define TONEMAPPING_NONE 0
+#define TONEMAPPING_REINHARD 1
+#define TONEMAPPING_ACES 2
+
+// Narkowicz's fit of the ACES filmic tone mapping curve
+vec3 aces(vec3 color) {
+    const float a = 2.51;
+    const float b = 0.03;
+    const float c = 2.43;
+    const float d = 0.59;
+    const float e = 0.14;
+    return clamp((color * (a * color + b)) / (color * (c * color + d) + e), 0.0, 1.0);
+}
+
+void main() {
+    // Sampled by coordinates rather than fragment position, as the quad may only cover
+    // a viewport of the window
+    vec4 hdr = texture(sampler2D(HdrTexture, HdrTextureSampler), v_Uv);
+    if (hdr.a <= 0.0) {
+        // Not covered by any chunk, keep whatever was drawn before
+        discard;
+    }
+    vec3 color = hdr.rgb / hdr.a * Exposure;
+    if (TonemappingOperator == TONEMAPPING_REINHARD) {
+        color = color / (1.0 + color);
+    } else if (TonemappingOperator == TONEMAPPING_ACES) {
+        color = aces(color);
+    }
+    f_color = vec4(color, min(hdr.a, 1.0));
+}
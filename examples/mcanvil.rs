@@ -5,14 +5,17 @@ use bevy::log::LogPlugin;
 use bevy::prelude::*;
 use bevy_fly_camera::{FlyCamera, FlyCameraPlugin};
 use bevy_sky::SkyPlugin;
-use ray_tracing::lights::SunLight;
+use ray_tracing::lights::{PointLight, Sky, SunLight};
 use ray_tracing::material::texture_repo::TextureRepo;
 use ray_tracing::material::{
-    ColoredMaterial, Material, MaterialPalette, DEFAULT_MATERIAL_PALETTE_HANDLE,
+    ColoredMaterial, FaceTextures, Material, MaterialPalette, Transparency,
+    DEFAULT_MATERIAL_PALETTE_HANDLE,
 };
 use ray_tracing::raytracer::chunk::{Chunk, ChunkBundle};
+use ray_tracing::raytracer::screenshot::Screenshot;
+use ray_tracing::raytracer::settings::RayTracerSettings;
 use ray_tracing::OctreeRayTracerPlugin;
-use ray_tracing::Voxel;
+use ray_tracing::{Axis, Face, Orientation, Voxel};
 use svo::octree::Octree;
 
 /// This example illustrates how to load shaders such that they can be
@@ -41,6 +44,8 @@ fn main() {
         .add_plugin(OctreeRayTracerPlugin::default())
         .add_plugin(SkyPlugin)
         .add_system(my_system.system())
+        .add_system(screenshot_system.system())
+        .add_system(heatmap_system.system())
         .run();
 }
 
@@ -49,53 +54,90 @@ fn setup(
     mut chunks: ResMut<Assets<Chunk>>,
     mut texture_repo: ResMut<TextureRepo>,
     mut material_palettes: ResMut<Assets<MaterialPalette>>,
+    mut sky: ResMut<Sky>,
 ) {
+    // A blue sky, seen in reflections and lighting the terrain along with the ambient light.
+    // It isn't taken from `SkyPlugin`, which draws the background: these colors are picked by
+    // hand to roughly match its daytime sky, and need updating if that changes.
+    *sky = Sky {
+        zenith_color: Color::rgb_linear(0.05, 0.1, 0.25),
+        horizon_color: Color::rgb_linear(0.15, 0.2, 0.25),
+        ground_color: Color::rgb_linear(0.05, 0.04, 0.03),
+    };
     let mut colored_material = ColoredMaterial::default();
     colored_material.color_palette[1] = Color::BLUE;
     colored_material.color_palette[2] = Color::YELLOW;
     colored_material.color_palette[3] = Color::RED;
+    // Glowstone and lava
+    colored_material.emission_palette[2] = 2.0;
+    colored_material.emission_palette[3] = 4.0;
     let scale: f32 = 1.0;
+    let dirt_texture = texture_repo.load("assets/textures/dirt.png");
     let stone_material = Material {
         name: "stone".into(),
         scale,
         diffuse: Some(texture_repo.load("assets/textures/stone.png")),
-        normal: None,
+        ..Default::default()
     };
     let log_material = Material {
         name: "log".into(),
         scale,
         diffuse: Some(texture_repo.load("assets/textures/log_oak.png")),
-        normal: None,
+        ..Default::default()
     };
-    let grass_material = ColoredMaterial {
-        material: Material {
-            name: "grass".into(),
-            scale,
-            diffuse: Some(texture_repo.load("assets/textures/grass.png")),
-            normal: None,
-        },
-        color_palette: [Color::GREEN; 256],
+    // Only the top of grass blocks is green
+    let grass_material = Material {
+        name: "grass".into(),
+        scale,
+        faces: FaceTextures::top_bottom_side(
+            texture_repo.load_tinted("assets/textures/grass.png", Color::rgb(0.57, 0.74, 0.35)),
+            dirt_texture,
+            dirt_texture,
+        ),
+        ..Default::default()
     };
     let leaves_material = ColoredMaterial {
         material: Material {
             name: "leaves".into(),
             scale,
             diffuse: Some(texture_repo.load("assets/textures/leaves_oak.png")),
-            normal: None,
+            transparency: Transparency::Mask(0.5),
+            ..Default::default()
         },
         color_palette: [Color::GREEN; 256],
+        ..Default::default()
+    };
+    let water_material = ColoredMaterial {
+        material: Material {
+            name: "water".into(),
+            scale: 0.0,
+            reflectivity: 0.02,
+            roughness: 0.0,
+            transparency: Transparency::Blend,
+            ..Default::default()
+        },
+        color_palette: [Color::rgba(0.1, 0.3, 0.8, 0.6); 256],
+        ..Default::default()
     };
     let dirt_material = Material {
         name: "dirt".into(),
         scale,
-        diffuse: Some(texture_repo.load("assets/textures/dirt.png")),
-        normal: None,
+        diffuse: Some(dirt_texture),
+        ..Default::default()
     };
     let sand_material = Material {
         name: "sand".into(),
         scale,
         diffuse: Some(texture_repo.load("assets/textures/sand.png")),
-        normal: None,
+        ..Default::default()
+    };
+    let glass_material = Material {
+        name: "glass".into(),
+        scale,
+        reflectivity: 0.04,
+        ior: 1.5,
+        roughness: 0.0,
+        ..Default::default()
     };
 
     let palette = material_palettes
@@ -108,12 +150,14 @@ fn setup(
     println!("Using region dir {}", region_dir);
 
     let colored_voxel = palette.add_colored_material(colored_material);
-    let grass_voxel = palette.add_colored_material(grass_material);
+    let grass_voxel = palette.add_material(grass_material);
     let leaves_voxel = palette.add_colored_material(leaves_material);
+    let water_voxel = palette.add_colored_material(water_material);
     let stone_voxel = palette.add_material(stone_material);
     let log_voxel = palette.add_material(log_material);
     let dirt_voxel = palette.add_material(dirt_material);
     let sand_voxel = palette.add_material(sand_material);
+    let glass_voxel = palette.add_material(glass_material);
 
     let args: Vec<_> = std::env::args().skip(1).collect();
     assert_eq!(args.len(), 1, "Format: mcanvil <mca filepath>");
@@ -124,6 +168,7 @@ fn setup(
         let mut region = fastanvil::Region::new(file);
 
         let mut octree: Octree<Voxel> = Octree::new();
+        let mut torches: Vec<Vec3> = Vec::new();
         region
             .for_each_chunk(|chunk_x, chunk_z, chunk_data| {
                 println!("loading chunk {} {}", chunk_x, chunk_z);
@@ -167,14 +212,36 @@ fn setup(
                                     "minecraft:oak_leaves" => leaves_voxel,
                                     "minecraft:acacia_leaves" => leaves_voxel,
                                     "minecraft:dirt" => dirt_voxel,
-                                    "minecraft:water" => colored_voxel.with_color(1),
+                                    "minecraft:water" => water_voxel,
                                     "minecraft:sand" => sand_voxel,
+                                    "minecraft:glass" => glass_voxel,
                                     "minecraft:lava" => colored_voxel.with_color(3),
+                                    "minecraft:glowstone" => colored_voxel.with_color(2),
+                                    "minecraft:torch" | "minecraft:wall_torch" => {
+                                        torches.push(Vec3::new(
+                                            (region_x * 512) as f32
+                                                + (x + chunk_x as u32 * 16) as f32
+                                                + 0.5,
+                                            y as f32 + 0.7,
+                                            (region_y * 512) as f32
+                                                + (z + chunk_z as u32 * 16) as f32
+                                                + 0.5,
+                                        ));
+                                        continue;
+                                    }
                                     _ => {
                                         //println!("Missing block: w {:?}", block.name);
                                         colored_voxel
                                     }
                                 };
+                                let properties = block.properties.as_ref();
+                                let axis = properties
+                                    .and_then(|properties| properties.get("axis"))
+                                    .map(|value| &**value);
+                                let facing = properties
+                                    .and_then(|properties| properties.get("facing"))
+                                    .map(|value| &**value);
+                                let voxel = voxel.with_orientation(block_orientation(axis, facing));
                                 octree.set(
                                     x + chunk_x as u32 * 16,
                                     y,
@@ -195,6 +262,18 @@ fn setup(
         );
         let chunk_handle = chunks.add(chunk);
         commands.spawn(ChunkBundle::new(chunk_handle));
+        for torch in torches {
+            commands.spawn((
+                PointLight {
+                    color: Color::rgb(1.0, 0.7, 0.4),
+                    intensity: 20.0,
+                    range: 14.0,
+                    shadows: true,
+                },
+                Transform::from_translation(torch),
+                GlobalTransform::from_translation(torch),
+            ));
+        }
     };
 
     load_region(1, 0);
@@ -216,8 +295,38 @@ fn setup(
         });
 }
 
+/// Orientation of a block from its `axis` or `facing` block state property
+fn block_orientation(axis: Option<&str>, facing: Option<&str>) -> Orientation {
+    match (axis, facing) {
+        (Some("x"), _) => Orientation::from_axis(Axis::X),
+        (Some("z"), _) => Orientation::from_axis(Axis::Z),
+        (_, Some("east")) => Orientation::facing(Face::East),
+        (_, Some("west")) => Orientation::facing(Face::West),
+        (_, Some("up")) => Orientation::facing(Face::Top),
+        (_, Some("down")) => Orientation::facing(Face::Bottom),
+        (_, Some("south")) => Orientation::facing(Face::South),
+        _ => Orientation::default(),
+    }
+}
+
 fn my_system(mut sun_light_resource: ResMut<SunLight>, time: Res<Time>) {
     sun_light_resource.direction.x = (time.seconds_since_startup()).cos() as f32;
     sun_light_resource.direction.z = (time.seconds_since_startup()).sin() as f32;
     sun_light_resource.direction.y = -1.0;
 }
+
+/// Saves the voxels on screen when F12 is pressed
+fn screenshot_system(keys: Res<Input<KeyCode>>, mut screenshots: ResMut<Events<Screenshot>>) {
+    if keys.just_pressed(KeyCode::F12) {
+        screenshots.send(Screenshot {
+            path: "screenshot.png".into(),
+        });
+    }
+}
+
+/// Switches between the shaded voxels and the heatmap of the octree traversal with H
+fn heatmap_system(keys: Res<Input<KeyCode>>, mut settings: ResMut<RayTracerSettings>) {
+    if keys.just_pressed(KeyCode::H) {
+        settings.iteration_heatmap = !settings.iteration_heatmap;
+    }
+}
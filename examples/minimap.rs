@@ -0,0 +1,127 @@
+use bevy::prelude::*;
+use bevy::render::camera::{Camera, OrthographicProjection, PerspectiveProjection};
+use bevy::render::render_graph::base::camera::CAMERA_3D;
+use bevy::render::render_graph::RenderGraph;
+use bevy::window::WindowId;
+use bevy_fly_camera::{FlyCamera, FlyCameraPlugin};
+use ray_tracing::material::{ColoredMaterial, MaterialPalette, DEFAULT_MATERIAL_PALETTE_HANDLE};
+use ray_tracing::raytracer::chunk::{Chunk, ChunkBundle};
+use ray_tracing::raytracer::target::RayPassTarget;
+use ray_tracing::raytracer::{add_ray_pass_camera, camera_node_name, node};
+use ray_tracing::OctreeRayTracerPlugin;
+use ray_tracing::Voxel;
+use svo::octree::Octree;
+
+const MINIMAP: &str = "minimap";
+
+/// A second camera drawing the voxels seen from above into a corner of the window,
+/// following the fly camera. Unlike the 3d camera, it has no history of the previous
+/// frames, so it is neither anti-aliased nor accumulating global illumination.
+fn main() {
+    let mut app = App::build();
+    app
+        // Bevy plugins
+        .add_plugin(bevy::reflect::ReflectPlugin::default())
+        .add_plugin(bevy::core::CorePlugin::default())
+        .add_plugin(bevy::transform::TransformPlugin::default())
+        .add_plugin(bevy::diagnostic::DiagnosticsPlugin::default())
+        .add_plugin(bevy::input::InputPlugin::default())
+        .add_plugin(bevy::window::WindowPlugin::default())
+        .add_plugin(bevy::asset::AssetPlugin::default())
+        .add_plugin(bevy::render::RenderPlugin::default())
+        .add_plugin(bevy::winit::WinitPlugin::default())
+        .add_plugin(bevy::wgpu::WgpuPlugin::default())
+        // Custom plugins
+        .add_plugin(FlyCameraPlugin)
+        .add_startup_system(setup.system())
+        .add_plugin(OctreeRayTracerPlugin::default())
+        .add_system(follow_system.system());
+
+    add_ray_pass_camera(
+        app.resources(),
+        MINIMAP,
+        RayPassTarget::Viewport {
+            window: WindowId::primary(),
+            viewport: [0.7, 0.05, 0.25, 0.25],
+        },
+    );
+    // Drawn over the view of the 3d camera
+    app.resources()
+        .get_mut::<RenderGraph>()
+        .unwrap()
+        .add_node_edge(
+            camera_node_name(CAMERA_3D, node::TONEMAPPING_PASS),
+            camera_node_name(MINIMAP, node::TONEMAPPING_PASS),
+        )
+        .unwrap();
+    app.run();
+}
+
+fn setup(
+    commands: &mut Commands,
+    mut chunks: ResMut<Assets<Chunk>>,
+    mut material_palettes: ResMut<Assets<MaterialPalette>>,
+) {
+    let palette = material_palettes
+        .get_mut(DEFAULT_MATERIAL_PALETTE_HANDLE)
+        .unwrap();
+    let mut colored_material = ColoredMaterial::default();
+    colored_material.color_palette[0] = Color::rgb(0.4, 0.3, 0.2);
+    colored_material.color_palette[1] = Color::rgb(0.3, 0.6, 0.2);
+    let voxel = palette.add_colored_material(colored_material);
+
+    // Hills of dirt covered with grass
+    let mut octree: Octree<Voxel> = Octree::new();
+    for x in 0..64u32 {
+        for z in 0..64u32 {
+            let height = 8.0 + 4.0 * (x as f32 * 0.2).sin() * (z as f32 * 0.15).cos();
+            let height = height as u32;
+            for y in 0..height {
+                let color = if y + 1 == height { 1 } else { 0 };
+                octree.set(x, y, z, 64, voxel.with_color(color));
+            }
+        }
+    }
+    let chunk = Chunk::new(octree, Vec4::new(-32.0, -32.0, -32.0, 64.0));
+
+    commands
+        .spawn(ChunkBundle::new(chunks.add(chunk)))
+        .spawn(PerspectiveCameraBundle {
+            transform: Transform::from_translation(Vec3::new(0.0, 0.0, 40.0))
+                .looking_at(Vec3::default(), Vec3::unit_y()),
+            perspective_projection: PerspectiveProjection {
+                near: 0.1,
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .with(FlyCamera::default())
+        .spawn(OrthographicCameraBundle {
+            camera: Camera {
+                name: Some(MINIMAP.to_string()),
+                ..OrthographicCameraBundle::new_3d().camera
+            },
+            orthographic_projection: OrthographicProjection {
+                scale: 0.1,
+                ..OrthographicCameraBundle::new_3d().orthographic_projection
+            },
+            ..OrthographicCameraBundle::new_3d()
+        });
+}
+
+/// Keeps the minimap camera above the fly camera, looking down with north up
+fn follow_system(
+    fly_cameras: Query<&Transform, With<FlyCamera>>,
+    mut cameras: Query<(&Camera, &mut Transform), Without<FlyCamera>>,
+) {
+    let position = match fly_cameras.iter().next() {
+        Some(transform) => transform.translation,
+        None => return,
+    };
+    for (camera, mut transform) in cameras.iter_mut() {
+        if camera.name.as_deref() == Some(MINIMAP) {
+            *transform = Transform::from_translation(position + Vec3::new(0.0, 64.0, 0.0))
+                .looking_at(position, -Vec3::unit_z());
+        }
+    }
+}
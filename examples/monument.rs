@@ -9,6 +9,7 @@ use ray_tracing::material::{
     ColoredMaterial, Material, MaterialPalette, DEFAULT_MATERIAL_PALETTE_HANDLE,
 };
 use ray_tracing::raytracer::chunk::{Chunk, ChunkBundle};
+use ray_tracing::raytracer::settings::RayTracerSettings;
 use ray_tracing::OctreeRayTracerPlugin;
 use ray_tracing::Voxel;
 use svo::octree::Octree;
@@ -38,6 +39,7 @@ fn main() {
         .insert_resource(TextureRepo::new(512, 512))
         .add_plugin(OctreeRayTracerPlugin::default())
         .add_system(my_system.system())
+        .add_system(heatmap_system.system())
         .run();
 }
 
@@ -70,12 +72,31 @@ fn setup(
         );
         colored_material.color_palette[i] = color;
     }
+    // Emissive MagicaVoxel materials. Material ids are palette indices starting at 1.
+    for material in &monument.materials {
+        let index = material.id as usize;
+        if material.properties.get("_type").map(String::as_str) != Some("_emit")
+            || index == 0
+            || index > 256
+        {
+            continue;
+        }
+        let property = |name: &str| -> f32 {
+            material
+                .properties
+                .get(name)
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(0.0)
+        };
+        // `_emit` is the emission from 0 to 1, scaled up by the power `_flux`
+        colored_material.emission_palette[index - 1] =
+            property("_emit") * (1.0 + property("_flux"));
+    }
     let colored_voxel = palette.add_colored_material(colored_material);
     palette.add_material(Material {
         name: "".into(),
         scale: 0.0,
-        diffuse: None,
-        normal: None,
+        ..Default::default()
     });
     for voxel in &model.voxels {
         octree.set(
@@ -108,3 +129,10 @@ fn my_system(mut sun_light_resource: ResMut<SunLight>, time: Res<Time>) {
     sun_light_resource.direction.x = (time.seconds_since_startup()).cos() as f32;
     sun_light_resource.direction.z = (time.seconds_since_startup()).sin() as f32;
 }
+
+/// Switches between the shaded voxels and the heatmap of the octree traversal with H
+fn heatmap_system(keys: Res<Input<KeyCode>>, mut settings: ResMut<RayTracerSettings>) {
+    if keys.just_pressed(KeyCode::H) {
+        settings.iteration_heatmap = !settings.iteration_heatmap;
+    }
+}
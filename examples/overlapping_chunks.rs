@@ -0,0 +1,76 @@
+use bevy::prelude::*;
+use bevy::render::camera::PerspectiveProjection;
+use bevy_fly_camera::{FlyCamera, FlyCameraPlugin};
+use ray_tracing::material::texture_repo::TextureRepo;
+use ray_tracing::material::{ColoredMaterial, MaterialPalette, DEFAULT_MATERIAL_PALETTE_HANDLE};
+use ray_tracing::raytracer::chunk::{Chunk, ChunkBundle};
+use ray_tracing::OctreeRayTracerPlugin;
+use ray_tracing::Voxel;
+use svo::octree::Octree;
+
+/// Two chunks of blocks, the red one partially hiding the blue one behind it.
+/// Where they overlap on screen, the red blocks keep the same brightness as elsewhere.
+fn main() {
+    App::build()
+        // Bevy plugins
+        .add_plugin(bevy::reflect::ReflectPlugin::default())
+        .add_plugin(bevy::core::CorePlugin::default())
+        .add_plugin(bevy::transform::TransformPlugin::default())
+        .add_plugin(bevy::diagnostic::DiagnosticsPlugin::default())
+        .add_plugin(bevy::input::InputPlugin::default())
+        .add_plugin(bevy::window::WindowPlugin::default())
+        .add_plugin(bevy::asset::AssetPlugin::default())
+        .add_plugin(bevy::render::RenderPlugin::default())
+        .add_plugin(bevy::winit::WinitPlugin::default())
+        .add_plugin(bevy::wgpu::WgpuPlugin::default())
+        // Custom plugins
+        .add_plugin(FlyCameraPlugin)
+        .add_startup_system(setup.system())
+        .insert_resource(TextureRepo::new(16, 16))
+        .add_plugin(OctreeRayTracerPlugin::default())
+        .run();
+}
+
+/// A chunk filled with a block of `voxel`, leaving a margin of empty space around it
+fn block(voxel: Voxel) -> Octree<Voxel> {
+    let mut octree: Octree<Voxel> = Octree::new();
+    for x in 4..28u32 {
+        for y in 4..28u32 {
+            for z in 4..28u32 {
+                octree.set(x, y, z, 32, voxel);
+            }
+        }
+    }
+    octree
+}
+
+fn setup(
+    commands: &mut Commands,
+    mut chunks: ResMut<Assets<Chunk>>,
+    mut material_palettes: ResMut<Assets<MaterialPalette>>,
+) {
+    let palette = material_palettes
+        .get_mut(DEFAULT_MATERIAL_PALETTE_HANDLE)
+        .unwrap();
+    let mut colored_material = ColoredMaterial::default();
+    colored_material.color_palette[0] = Color::rgb(0.8, 0.2, 0.2);
+    colored_material.color_palette[1] = Color::rgb(0.2, 0.2, 0.8);
+    let voxel = palette.add_colored_material(colored_material);
+
+    let front = Chunk::new(block(voxel), Vec4::new(-12.0, -8.0, 0.0, 16.0));
+    let back = Chunk::new(block(voxel.with_color(1)), Vec4::new(-4.0, -8.0, -24.0, 16.0));
+
+    commands
+        .spawn(ChunkBundle::new(chunks.add(front)))
+        .spawn(ChunkBundle::new(chunks.add(back)))
+        .spawn(PerspectiveCameraBundle {
+            transform: Transform::from_translation(Vec3::new(0.0, 0.0, 48.0))
+                .looking_at(Vec3::default(), Vec3::unit_y()),
+            perspective_projection: PerspectiveProjection {
+                near: 0.1,
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .with(FlyCamera::default());
+}
@@ -0,0 +1,54 @@
+use bevy::prelude::*;
+use bevy::render::camera::OrthographicProjection;
+use ray_tracing::material::{ColoredMaterial, MaterialPalette, DEFAULT_MATERIAL_PALETTE_HANDLE};
+use ray_tracing::raytracer::chunk::{Chunk, ChunkBundle};
+use ray_tracing::render_to_image;
+use ray_tracing::Voxel;
+use svo::octree::Octree;
+
+/// Renders an isometric thumbnail of a hill without opening any window,
+/// and saves it to thumbnail.png.
+fn main() {
+    let camera = OrthographicCameraBundle {
+        transform: Transform::from_translation(Vec3::new(40.0, 40.0, 40.0))
+            .looking_at(Vec3::default(), Vec3::unit_y()),
+        orthographic_projection: OrthographicProjection {
+            scale: 0.1,
+            ..OrthographicCameraBundle::new_3d().orthographic_projection
+        },
+        ..OrthographicCameraBundle::new_3d()
+    };
+    match render_to_image(setup.system(), camera, (256, 256)) {
+        Ok(image) => image.save("thumbnail.png").unwrap(),
+        Err(error) => eprintln!("{}", error),
+    }
+}
+
+fn setup(
+    commands: &mut Commands,
+    mut chunks: ResMut<Assets<Chunk>>,
+    mut material_palettes: ResMut<Assets<MaterialPalette>>,
+) {
+    let palette = material_palettes
+        .get_mut(DEFAULT_MATERIAL_PALETTE_HANDLE)
+        .unwrap();
+    let mut colored_material = ColoredMaterial::default();
+    colored_material.color_palette[0] = Color::rgb(0.4, 0.3, 0.2);
+    colored_material.color_palette[1] = Color::rgb(0.3, 0.6, 0.2);
+    let voxel = palette.add_colored_material(colored_material);
+
+    // A hill of dirt covered with grass, growing toward the center of the chunk
+    let mut octree: Octree<Voxel> = Octree::new();
+    for x in 0..64u32 {
+        for z in 0..64u32 {
+            let distance = (x as f32 - 32.0).hypot(z as f32 - 32.0);
+            let height = (24.0 - distance * 0.6).max(1.0) as u32;
+            for y in 0..height {
+                let color = if y + 1 == height { 1 } else { 0 };
+                octree.set(x, y, z, 64, voxel.with_color(color));
+            }
+        }
+    }
+    let chunk = Chunk::new(octree, Vec4::new(-16.0, -16.0, -16.0, 32.0));
+    commands.spawn(ChunkBundle::new(chunks.add(chunk)));
+}
@@ -1,9 +1,12 @@
 pub mod lights;
 pub mod material;
+mod orientation;
 pub mod raytracer;
 pub mod wgpu_extract;
 
+pub use orientation::{Axis, Face, Orientation};
 pub use raytracer::chunk_node::ChunkNode;
+pub use raytracer::headless::render_to_image;
 pub use raytracer::OctreeRayTracerPlugin;
 pub use raytracer::RayPass;
 
@@ -12,9 +15,13 @@ use std::fmt::{Debug, Formatter, Result};
 #[derive(Copy, Clone, Default, Eq, PartialEq, PartialOrd, Ord)]
 pub struct Voxel(u16);
 
+const REGULAR_ID_MASK: u16 = 0x3ff;
+const ORIENTATION_SHIFT: u16 = 10;
+
 // 0:  Air
-// 0x01 - 0x80: 2^15 - 1 = 32767 standarlone blocks
-// 0x80 - 0xFF:
+// 0x0001 - 0x7FFF: standalone blocks. Bits 0 - 9 hold the material id 1 - 1023,
+//                  bits 10 - 14 the orientation.
+// 0x8000 - 0xFFFF: colored blocks. Bits 8 - 14 hold the material id, bits 0 - 7 the color.
 #[derive(Debug)]
 pub enum VoxelData {
     Regular(u16),
@@ -26,13 +33,15 @@ impl Voxel {
         let id = id | 0x80;
         Voxel(((id as u16) << 8) | (color as u16))
     }
+    /// A regular voxel of the material `id` of the palette. Ids go from 1 to 1023, as the
+    /// upper bits hold the orientation, and 0 is air.
     pub fn new(id: u16) -> Self {
-        assert_eq!(id & 0x8000, 0, "Colored voxel has index 0 - 0x8f");
+        assert_eq!(id & !REGULAR_ID_MASK, 0, "Regular voxel has index 0 - 0x3ff");
         Voxel(id)
     }
     pub fn get(&self) -> VoxelData {
         if self.0 & 0x8000 == 0 {
-            VoxelData::Regular(self.0)
+            VoxelData::Regular(self.0 & REGULAR_ID_MASK)
         } else {
             let voxel_id = (self.0 >> 8) as u8 & 0x7f;
             let color = (self.0 & 0xff) as u8;
@@ -45,6 +54,21 @@ impl Voxel {
             VoxelData::Colored(id, _) => Voxel::new_colored(id, color),
         }
     }
+    /// Rotates regular voxels. Colored voxels are left unchanged.
+    pub fn with_orientation(&self, orientation: Orientation) -> Voxel {
+        match self.get() {
+            VoxelData::Regular(id) => {
+                Voxel(id | ((orientation.bits() as u16) << ORIENTATION_SHIFT))
+            }
+            VoxelData::Colored(_, _) => *self,
+        }
+    }
+    pub fn orientation(&self) -> Orientation {
+        match self.get() {
+            VoxelData::Regular(_) => Orientation::from_bits((self.0 >> ORIENTATION_SHIFT) as u8),
+            VoxelData::Colored(_, _) => Orientation::default(),
+        }
+    }
 }
 
 impl svo::Voxel for Voxel {
@@ -6,21 +6,119 @@ pub mod node;
 #[reflect(Component)]
 pub struct PointLight {
     pub color: Color,
+    pub intensity: f32,
+    /// Distance at which the light fades out completely
+    pub range: f32,
+    /// Trace shadow rays toward the light. Shadows are only cast by voxels within the same chunk.
+    pub shadows: bool,
 }
 
 impl Default for PointLight {
     fn default() -> Self {
         PointLight {
             color: Color::rgb(1.0, 1.0, 1.0),
+            intensity: 10.0,
+            range: 16.0,
+            shadows: false,
         }
     }
 }
 
+/// A spot light shining along the forward (-Z) axis of its transform
+#[derive(Debug, Reflect)]
+#[reflect(Component)]
+pub struct SpotLight {
+    pub color: Color,
+    pub intensity: f32,
+    /// Distance at which the light fades out completely
+    pub range: f32,
+    /// Angle from the axis, in radians, within which the light has full intensity
+    pub inner_angle: f32,
+    /// Angle from the axis, in radians, beyond which the light has no effect
+    pub outer_angle: f32,
+    /// Trace shadow rays toward the light. Shadows are only cast by voxels within the same chunk.
+    pub shadows: bool,
+}
+
+impl Default for SpotLight {
+    fn default() -> Self {
+        SpotLight {
+            color: Color::rgb(1.0, 1.0, 1.0),
+            intensity: 10.0,
+            range: 16.0,
+            inner_angle: 0.4,
+            outer_angle: 0.6,
+            shadows: false,
+        }
+    }
+}
+
+/// A one sided rectangular light shining along the forward (-Z) axis of its transform.
+/// The rectangle extends along the X and Y axes of the transform.
+/// Its shadows are softened by sampling random points on the rectangle.
+#[derive(Debug, Reflect)]
+#[reflect(Component)]
+pub struct AreaLight {
+    pub color: Color,
+    pub intensity: f32,
+    /// Distance at which the light fades out completely
+    pub range: f32,
+    pub width: f32,
+    pub height: f32,
+    /// Trace shadow rays toward the light. Shadows are only cast by voxels within the same chunk.
+    pub shadows: bool,
+}
+
+impl Default for AreaLight {
+    fn default() -> Self {
+        AreaLight {
+            color: Color::rgb(1.0, 1.0, 1.0),
+            intensity: 10.0,
+            range: 16.0,
+            width: 1.0,
+            height: 1.0,
+            shadows: false,
+        }
+    }
+}
+
+/// A directional light lighting every chunk. Shadows are configured with
+/// [RayTracerSettings](crate::raytracer::settings::RayTracerSettings).
+#[derive(Debug, Clone, PartialEq)]
 pub struct SunLight {
     pub color: Color,
+    /// The direction the light travels in, pointing away from the sun
     pub direction: Vec3,
 }
 
+#[derive(Debug, Clone, PartialEq)]
 pub struct AmbientLight {
     pub color: Color,
 }
+
+/// A gradient of light coming from the sky. Reflected, refracted and bounce rays leaving
+/// the chunks see it, and it lights surfaces along with the [AmbientLight].
+/// The default black sky adds no light.
+///
+/// This is independent of the background primary rays reveal when they leave the chunks,
+/// such as the sky drawn by `bevy_sky`. Nothing keeps the two in sync: set the colors to
+/// match that background yourself, or reflections will show a different sky.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sky {
+    /// Color straight up
+    pub zenith_color: Color,
+    /// Color at the horizon, fading into `zenith_color` above and `ground_color` below
+    pub horizon_color: Color,
+    /// Color straight down
+    pub ground_color: Color,
+}
+
+impl Default for Sky {
+    fn default() -> Self {
+        Sky {
+            zenith_color: Color::BLACK,
+            horizon_color: Color::BLACK,
+            ground_color: Color::BLACK,
+        }
+    }
+}
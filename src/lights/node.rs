@@ -1,24 +1,151 @@
-use crate::lights::{AmbientLight, PointLight, SunLight};
-use bevy::core::AsBytes;
+use crate::lights::{AmbientLight, AreaLight, PointLight, Sky, SpotLight, SunLight};
+use crate::raytracer::chunk::Chunk;
+use bevy::core::{AsBytes, Bytes};
 use bevy::prelude::*;
 use bevy::render::render_graph::{CommandQueue, Node, ResourceSlots, SystemNode};
 use bevy::render::renderer::{
     BufferId, BufferInfo, BufferMapMode, BufferUsage, RenderContext, RenderResourceBinding,
     RenderResourceBindings, RenderResourceContext,
 };
+use bevy::wgpu::renderer::BIND_BUFFER_ALIGNMENT;
+use std::collections::HashMap;
+use std::ops::Range;
 
 const LIGHTS: &str = "Lights";
+
+/// A light in the `Lights` storage buffer, with the std430 layout.
+/// ```glsl
+/// struct Light {
+///     vec4 color; // rgb: color * intensity, a: range
+///     vec4 position; // xyz: position, w: light kind
+///     vec4 direction; // xyz: spot light direction or area light normal, w: 1.0 if casting shadows
+///     vec4 params; // spot light: cos(inner angle), cos(outer angle). area light: half width, half height
+///     vec4 tangent; // area light: xyz axis along the width
+/// };
+/// ```
+pub struct LightData {
+    color: [f32; 4],
+    position: [f32; 4],
+    direction: [f32; 4],
+    params: [f32; 4],
+    tangent: [f32; 4],
+}
+const LIGHT_SIZE: usize = std::mem::size_of::<[f32; 20]>();
+
+const LIGHT_KIND_POINT: f32 = 0.0;
+const LIGHT_KIND_SPOT: f32 = 1.0;
+const LIGHT_KIND_AREA: f32 = 2.0;
+
+impl LightData {
+    fn new(
+        kind: f32,
+        color: Color,
+        intensity: f32,
+        range: f32,
+        shadows: bool,
+        global_transform: &GlobalTransform,
+    ) -> Self {
+        let forward = global_transform.rotation * -Vec3::unit_z();
+        LightData {
+            color: [
+                color.r_linear() * intensity,
+                color.g_linear() * intensity,
+                color.b_linear() * intensity,
+                range,
+            ],
+            position: global_transform.translation.extend(kind).into(),
+            direction: forward.extend(if shadows { 1.0 } else { 0.0 }).into(),
+            params: [0.0; 4],
+            tangent: [0.0; 4],
+        }
+    }
+    pub fn point(light: &PointLight, global_transform: &GlobalTransform) -> Self {
+        LightData::new(
+            LIGHT_KIND_POINT,
+            light.color,
+            light.intensity,
+            light.range,
+            light.shadows,
+            global_transform,
+        )
+    }
+    pub fn spot(light: &SpotLight, global_transform: &GlobalTransform) -> Self {
+        let mut data = LightData::new(
+            LIGHT_KIND_SPOT,
+            light.color,
+            light.intensity,
+            light.range,
+            light.shadows,
+            global_transform,
+        );
+        data.params = [light.inner_angle.cos(), light.outer_angle.cos(), 0.0, 0.0];
+        data
+    }
+    pub fn area(light: &AreaLight, global_transform: &GlobalTransform) -> Self {
+        let mut data = LightData::new(
+            LIGHT_KIND_AREA,
+            light.color,
+            light.intensity,
+            light.range,
+            light.shadows,
+            global_transform,
+        );
+        data.params = [light.width / 2.0, light.height / 2.0, 0.0, 0.0];
+        data.tangent = (global_transform.rotation * Vec3::unit_x())
+            .extend(0.0)
+            .into();
+        data
+    }
+}
+
+impl LightData {
+    /// Whether the light can reach any point of an axis aligned box, given as origin and size
+    pub fn reaches(&self, bounding_box: Vec4) -> bool {
+        let position = Vec3::new(self.position[0], self.position[1], self.position[2]);
+        let min = bounding_box.truncate();
+        let max = min + Vec3::splat(bounding_box.w);
+        let mut reach = self.color[3];
+        if self.position[3] == LIGHT_KIND_AREA {
+            // Distances are measured from any point of the rectangle
+            reach += Vec2::new(self.params[0], self.params[1]).length();
+        }
+        let closest = position.max(min).min(max);
+        (closest - position).length_squared() < reach * reach
+    }
+}
+
+impl Bytes for LightData {
+    fn write_bytes(&self, buffer: &mut [u8]) {
+        let size = std::mem::size_of::<[f32; 4]>();
+        buffer[0..size].copy_from_slice(self.color.as_bytes());
+        buffer[size..size * 2].copy_from_slice(self.position.as_bytes());
+        buffer[size * 2..size * 3].copy_from_slice(self.direction.as_bytes());
+        buffer[size * 3..size * 4].copy_from_slice(self.params.as_bytes());
+        buffer[size * 4..size * 5].copy_from_slice(self.tangent.as_bytes());
+    }
+    fn byte_len(&self) -> usize {
+        LIGHT_SIZE
+    }
+}
+
+/// Number of lights the `Lights` buffer has room for when it is first created
+const INITIAL_LIGHT_CAPACITY: usize = 16;
+/// Size of the `Lights` buffer header, before the light array
+const LIGHTS_HEADER_SIZE: usize = std::mem::size_of::<[f32; 11]>()
+    + std::mem::size_of::<u32>()
+    + std::mem::size_of::<[f32; 12]>();
+
+const CHUNK_LIGHTS: &str = "ChunkLights";
+
 /// A Render Graph [Node] that write light data from the ECS to GPU buffers
 #[derive(Debug, Default)]
 pub struct LightsNode {
     command_queue: CommandQueue,
-    max_lights: usize,
 }
 
 impl LightsNode {
-    pub fn new(max_lights: usize) -> Self {
+    pub fn new() -> Self {
         LightsNode {
-            max_lights,
             command_queue: CommandQueue::default(),
         }
     }
@@ -44,74 +171,153 @@ impl SystemNode for LightsNode {
             system.id(),
             LightsNodeSystemState {
                 command_queue: self.command_queue.clone(),
-                max_lights: self.max_lights,
-                light_buffer: None,
-                staging_buffer: None,
+                lights: GrowableBuffer::default(),
+                chunk_lights: GrowableBuffer::default(),
+                chunk_bindings: HashMap::new(),
             },
         );
         Box::new(system)
     }
 }
 
+/// A GPU buffer written through a staging buffer, reallocated when the data outgrows it
+#[derive(Debug, Default)]
+struct GrowableBuffer {
+    buffer: Option<BufferId>,
+    staging_buffer: Option<BufferId>,
+    /// Size of both buffers in bytes
+    capacity: usize,
+}
+
+impl GrowableBuffer {
+    /// Makes room for `size` bytes and maps the staging buffer for writing.
+    /// Returns true when the buffers were reallocated, in which case the previous buffer
+    /// was removed and every binding to it has to be updated.
+    fn reserve(
+        &mut self,
+        render_resource_context: &dyn RenderResourceContext,
+        size: usize,
+        initial_capacity: usize,
+    ) -> bool {
+        if size <= self.capacity {
+            if let Some(staging_buffer) = self.staging_buffer {
+                render_resource_context.map_buffer(staging_buffer, BufferMapMode::Write);
+                return false;
+            }
+        }
+        let mut capacity = self.capacity.max(initial_capacity);
+        while capacity < size {
+            capacity *= 2;
+        }
+        if let Some(buffer) = self.buffer {
+            render_resource_context.remove_buffer(buffer);
+        }
+        if let Some(staging_buffer) = self.staging_buffer {
+            render_resource_context.remove_buffer(staging_buffer);
+        }
+        self.buffer = Some(render_resource_context.create_buffer(BufferInfo {
+            size: capacity,
+            buffer_usage: BufferUsage::STORAGE | BufferUsage::COPY_DST,
+            ..Default::default()
+        }));
+        self.staging_buffer = Some(render_resource_context.create_buffer(BufferInfo {
+            size: capacity,
+            buffer_usage: BufferUsage::COPY_SRC | BufferUsage::MAP_WRITE,
+            mapped_at_creation: true,
+        }));
+        self.capacity = capacity;
+        true
+    }
+
+    /// Unmaps the staging buffer and copies its first `size` bytes to the GPU buffer
+    fn submit(
+        &self,
+        render_resource_context: &dyn RenderResourceContext,
+        command_queue: &mut CommandQueue,
+        size: usize,
+    ) {
+        let staging_buffer = self.staging_buffer.unwrap();
+        render_resource_context.unmap_buffer(staging_buffer);
+        command_queue.copy_buffer_to_buffer(
+            staging_buffer,
+            0,
+            self.buffer.unwrap(),
+            0,
+            size as u64,
+        );
+    }
+}
+
 /// Local "lights node system" state
 #[derive(Debug, Default)]
 pub struct LightsNodeSystemState {
-    light_buffer: Option<BufferId>,
-    staging_buffer: Option<BufferId>,
+    /// All lights, bound globally as `Lights`
+    lights: GrowableBuffer,
+    /// Per chunk lists of indices into `Lights`, bound on each chunk as `ChunkLights`
+    chunk_lights: GrowableBuffer,
+    /// Range of `chunk_lights` each chunk is currently bound to
+    chunk_bindings: HashMap<Entity, (BufferId, Range<usize>)>,
     command_queue: CommandQueue,
-    max_lights: usize,
 }
 
+fn align_to(num: usize, alignment: usize) -> usize {
+    ((num + alignment - 1) / alignment) * alignment
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn lights_node_system(
     mut state: Local<LightsNodeSystemState>,
     render_resource_context: Res<Box<dyn RenderResourceContext>>,
     ambient_light_resource: Res<AmbientLight>,
     sun_light_resource: Res<SunLight>,
+    sky_resource: Res<Sky>,
+    chunks: Res<Assets<Chunk>>,
     // TODO: this write on RenderResourceBindings will prevent this system from running in parallel with other systems that do the same
     mut render_resource_bindings: ResMut<RenderResourceBindings>,
-    query: Query<(&PointLight, &GlobalTransform)>,
+    point_lights: Query<(&PointLight, &GlobalTransform)>,
+    spot_lights: Query<(&SpotLight, &GlobalTransform)>,
+    area_lights: Query<(&AreaLight, &GlobalTransform)>,
+    mut chunk_query: Query<(Entity, &Handle<Chunk>, &mut RenderPipelines)>,
 ) {
-    let state = &mut state;
+    let state = &mut *state;
     let render_resource_context = &**render_resource_context;
 
-    let point_light_count = query.iter().count() as u32;
-    let current_light_uniform_size = std::mem::size_of::<[f32; 11]>()
-        + std::mem::size_of::<u32>()
-        + std::mem::size_of::<PointLight>() * point_light_count as usize;
-    let max_light_uniform_size = std::mem::size_of::<[f32; 11]>()
-        + std::mem::size_of::<u32>()
-        + std::mem::size_of::<PointLight>() * state.max_lights;
-
-    if let Some(staging_buffer) = state.staging_buffer {
-        render_resource_context.map_buffer(staging_buffer, BufferMapMode::Write);
-    } else {
-        let buffer = render_resource_context.create_buffer(BufferInfo {
-            size: max_light_uniform_size,
-            buffer_usage: BufferUsage::UNIFORM | BufferUsage::COPY_DST,
-            ..Default::default()
-        });
+    let lights: Vec<LightData> = point_lights
+        .iter()
+        .map(|(light, global_transform)| LightData::point(light, global_transform))
+        .chain(
+            spot_lights
+                .iter()
+                .map(|(light, global_transform)| LightData::spot(light, global_transform)),
+        )
+        .chain(
+            area_lights
+                .iter()
+                .map(|(light, global_transform)| LightData::area(light, global_transform)),
+        )
+        .collect();
+    let light_count = lights.len();
+    let current_light_buffer_size = LIGHTS_HEADER_SIZE + LIGHT_SIZE * light_count;
+
+    if state.lights.reserve(
+        render_resource_context,
+        current_light_buffer_size,
+        LIGHTS_HEADER_SIZE + LIGHT_SIZE * INITIAL_LIGHT_CAPACITY,
+    ) {
+        // Rebinding marks the bind groups using the previous buffer as stale
         render_resource_bindings.set(
             LIGHTS,
             RenderResourceBinding::Buffer {
-                buffer,
-                range: 0..max_light_uniform_size as u64,
+                buffer: state.lights.buffer.unwrap(),
+                range: 0..state.lights.capacity as u64,
                 dynamic_index: None,
             },
         );
-        state.light_buffer = Some(buffer);
-
-        let staging_buffer = render_resource_context.create_buffer(BufferInfo {
-            size: max_light_uniform_size,
-            buffer_usage: BufferUsage::COPY_SRC | BufferUsage::MAP_WRITE,
-            mapped_at_creation: true,
-        });
-        state.staging_buffer = Some(staging_buffer);
     }
 
-    let staging_buffer = state.staging_buffer.unwrap();
     render_resource_context.write_mapped_buffer(
-        staging_buffer,
-        0..current_light_uniform_size as u64,
+        state.lights.staging_buffer.unwrap(),
+        0..current_light_buffer_size as u64,
         &mut |data, _renderer| {
             let mut current_size_head: usize = 0;
             let mut current_size_tail = std::mem::size_of::<[f32; 4]>();
@@ -149,35 +355,117 @@ pub fn lights_node_system(
             current_size_head = current_size_tail;
             current_size_tail += size;
             data[current_size_head..current_size_tail]
-                .copy_from_slice(point_light_count.as_bytes());
+                .copy_from_slice((light_count as u32).as_bytes());
+
+            // sky
+            for color in [
+                sky_resource.zenith_color,
+                sky_resource.horizon_color,
+                sky_resource.ground_color,
+            ]
+            .iter()
+            {
+                let color_data: [f32; 4] = [
+                    color.r_linear(),
+                    color.g_linear(),
+                    color.b_linear(),
+                    color.a(),
+                ];
+                let size = std::mem::size_of::<[f32; 4]>();
+                current_size_head = current_size_tail;
+                current_size_tail += size;
+                data[current_size_head..current_size_tail].copy_from_slice(color_data.as_bytes());
+            }
 
             // light array
-            for ((light, global_transform), slot) in query.iter().zip(
-                data[current_size_tail..current_light_uniform_size]
-                    .chunks_exact_mut(std::mem::size_of::<[f32; 8]>()),
+            for (light, slot) in lights.iter().zip(
+                data[current_size_tail..current_light_buffer_size].chunks_exact_mut(LIGHT_SIZE),
             ) {
-                let color: [f32; 4] = [
-                    light.color.r_linear(),
-                    light.color.g_linear(),
-                    light.color.b_linear(),
-                    light.color.a(),
-                ];
-                let color_size = std::mem::size_of::<[f32; 4]>();
-                slot[0..color_size].copy_from_slice(color.as_bytes());
+                light.write_bytes(slot);
+            }
+        },
+    );
+    state.lights.submit(
+        render_resource_context,
+        &mut state.command_queue,
+        current_light_buffer_size,
+    );
 
-                let pos: [f32; 3] = global_transform.translation.into();
-                let pos_size = std::mem::size_of::<[f32; 3]>();
-                slot[color_size..(color_size + pos_size)].copy_from_slice(pos.as_bytes());
+    // Cull lights per chunk so that fragments only evaluate the lights that can reach them.
+    // ```glsl
+    // layout(set = 2, binding = 3) readonly buffer ChunkLights {
+    //     uint ChunkLightCount;
+    //     uint ChunkLightIndices[];
+    // };
+    // ```
+    // The lists of all chunks are packed into one buffer, each one starting at an offset
+    // that can be bound.
+    let mut chunk_light_lists: Vec<(Entity, Vec<u32>, Range<usize>)> = Vec::new();
+    let mut chunk_lights_size = 0;
+    for (entity, chunk_handle, _) in chunk_query.iter_mut() {
+        let indices: Vec<u32> = match chunks.get(chunk_handle) {
+            Some(chunk) => lights
+                .iter()
+                .enumerate()
+                .filter(|(_, light)| light.reaches(chunk.bounding_box))
+                .map(|(i, _)| i as u32)
+                .collect(),
+            None => Vec::new(),
+        };
+        let size = std::mem::size_of::<u32>() * (indices.len() + 1);
+        let range = chunk_lights_size..chunk_lights_size + size;
+        chunk_lights_size = align_to(range.end, BIND_BUFFER_ALIGNMENT as usize);
+        chunk_light_lists.push((entity, indices, range));
+    }
+    // Forget the bindings of despawned chunks
+    state
+        .chunk_bindings
+        .retain(|entity, _| chunk_light_lists.iter().any(|(chunk, _, _)| chunk == entity));
+    if chunk_light_lists.is_empty() {
+        return;
+    }
+
+    state.chunk_lights.reserve(
+        render_resource_context,
+        chunk_lights_size,
+        BIND_BUFFER_ALIGNMENT as usize,
+    );
+    render_resource_context.write_mapped_buffer(
+        state.chunk_lights.staging_buffer.unwrap(),
+        0..chunk_lights_size as u64,
+        &mut |data, _renderer| {
+            for (_, indices, range) in chunk_light_lists.iter() {
+                let data = &mut data[range.clone()];
+                (indices.len() as u32).write_bytes(&mut data[0..4]);
+                data[4..].copy_from_slice(indices.as_bytes());
             }
         },
     );
-    render_resource_context.unmap_buffer(staging_buffer);
-    let light_buffer = state.light_buffer.unwrap();
-    state.command_queue.copy_buffer_to_buffer(
-        staging_buffer,
-        0,
-        light_buffer,
-        0,
-        current_light_uniform_size as u64,
+    state.chunk_lights.submit(
+        render_resource_context,
+        &mut state.command_queue,
+        chunk_lights_size,
     );
+
+    // Ranges move when the number of lights in a chunk before them changes, and the buffer
+    // when it is reallocated. Only the chunks whose binding moved are rebound, as rebinding
+    // recreates their bind groups.
+    let chunk_light_buffer = state.chunk_lights.buffer.unwrap();
+    for (entity, _, range) in chunk_light_lists {
+        let binding = (chunk_light_buffer, range);
+        if state.chunk_bindings.get(&entity) == Some(&binding) {
+            continue;
+        }
+        if let Ok((_, _, mut render_pipelines)) = chunk_query.get_mut(entity) {
+            render_pipelines.bindings.set(
+                CHUNK_LIGHTS,
+                RenderResourceBinding::Buffer {
+                    buffer: chunk_light_buffer,
+                    range: binding.1.start as u64..binding.1.end as u64,
+                    dynamic_index: None,
+                },
+            );
+        }
+        state.chunk_bindings.insert(entity, binding);
+    }
 }
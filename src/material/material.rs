@@ -17,7 +17,106 @@ pub struct Material {
     pub name: Cow<'static, str>,
     pub scale: f32,
     pub diffuse: Option<TextureRepoHandle>,
+    /// Diffuse textures of individual faces, replacing `diffuse` on those faces
+    pub faces: FaceTextures,
     pub normal: Option<TextureRepoHandle>,
+    /// Fraction of the light reflected like a mirror, from 0 to 1
+    pub reflectivity: f32,
+    /// Index of refraction of transparent materials like glass (1.5) or water (1.33).
+    /// Materials with an index of refraction of 0 are opaque.
+    pub ior: f32,
+    /// Spread of the reflections and size of the highlights, from 0 for polished surfaces
+    /// like glass, water or mirrors, to 1 for matte surfaces. Defaults to 0.8, for rough
+    /// surfaces like stone or wood.
+    pub roughness: f32,
+    /// 1 for metals, whose base color tints their reflections, 0 for other materials
+    pub metallic: f32,
+    /// Multiplies `roughness` by the green channel and `metallic` by the blue channel,
+    /// like glTF. Only diffuse textures are decoded from sRGB by the shader, so load it as is
+    /// with [TextureRepo::load](super::texture_repo::TextureRepo::load).
+    pub metallic_roughness: Option<TextureRepoHandle>,
+    pub transparency: Transparency,
+    /// Light emitted by the surface, added to its shaded color.
+    /// Components above 1 make the surface glow brighter.
+    pub emission: Color,
+}
+
+/// Textures of the six faces of a voxel, named after the Minecraft directions
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct FaceTextures {
+    /// +X
+    pub east: Option<TextureRepoHandle>,
+    /// -X
+    pub west: Option<TextureRepoHandle>,
+    /// +Y
+    pub top: Option<TextureRepoHandle>,
+    /// -Y
+    pub bottom: Option<TextureRepoHandle>,
+    /// +Z
+    pub south: Option<TextureRepoHandle>,
+    /// -Z
+    pub north: Option<TextureRepoHandle>,
+}
+
+impl FaceTextures {
+    pub fn top_bottom_side(
+        top: TextureRepoHandle,
+        bottom: TextureRepoHandle,
+        side: TextureRepoHandle,
+    ) -> Self {
+        FaceTextures {
+            east: Some(side),
+            west: Some(side),
+            top: Some(top),
+            bottom: Some(bottom),
+            south: Some(side),
+            north: Some(side),
+        }
+    }
+    /// The textures in the order of the faces in the shader: +X, -X, +Y, -Y, +Z, -Z
+    fn to_array(self) -> [Option<TextureRepoHandle>; 6] {
+        [
+            self.east,
+            self.west,
+            self.top,
+            self.bottom,
+            self.south,
+            self.north,
+        ]
+    }
+}
+
+/// How the alpha of the palette colors and the diffuse texture is used
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Transparency {
+    /// Alpha is ignored
+    Opaque,
+    /// Rays go through the voxels, blending in their color by its alpha.
+    /// Adjacent voxels of the same material are blended once, like a single volume.
+    Blend,
+    /// Texels with an alpha below the cutoff are holes, the others are opaque
+    Mask(f32),
+}
+
+impl Default for Transparency {
+    fn default() -> Self {
+        Transparency::Opaque
+    }
+}
+
+impl Bytes for Transparency {
+    fn write_bytes(&self, buffer: &mut [u8]) {
+        let (mode, cutoff): (u32, f32) = match *self {
+            Transparency::Opaque => (0, 0.0),
+            Transparency::Blend => (1, 0.0),
+            Transparency::Mask(cutoff) => (2, cutoff),
+        };
+        mode.write_bytes(&mut buffer[0..4]);
+        cutoff.write_bytes(&mut buffer[4..8]);
+    }
+    fn byte_len(&self) -> usize {
+        8
+    }
 }
 
 #[derive(TypeUuid)]
@@ -25,10 +124,14 @@ pub struct Material {
 pub struct ColoredMaterial {
     pub material: Material,
     pub color_palette: [Color; 256],
+    /// Strength of the light emitted by each palette color, added to the emission of the material
+    pub emission_palette: [f32; 256],
 }
 
-const MATERIAL_DATA_SIZE: usize = 16;
-const COLORED_MATERIAL_DATA_SIZE: usize = MATERIAL_DATA_SIZE + std::mem::size_of::<[Color; 256]>();
+const MATERIAL_DATA_SIZE: usize = 64;
+const COLOR_PALETTE_SIZE: usize = std::mem::size_of::<[Color; 256]>();
+const COLORED_MATERIAL_DATA_SIZE: usize =
+    MATERIAL_DATA_SIZE + COLOR_PALETTE_SIZE + std::mem::size_of::<[f32; 256]>();
 #[derive(TypeUuid, Debug)]
 #[uuid = "6ac654c6-607f-426f-98b5-2e7f6d810056"]
 pub struct MaterialPalette {
@@ -43,12 +146,52 @@ impl Bytes for Material {
         self.scale.write_bytes(&mut buffer[0..4]);
         self.diffuse.write_bytes(&mut buffer[4..6]);
         self.normal.write_bytes(&mut buffer[6..8]);
+        self.reflectivity.write_bytes(&mut buffer[8..12]);
+        self.ior.write_bytes(&mut buffer[12..16]);
+        self.roughness.write_bytes(&mut buffer[16..20]);
+        self.transparency.write_bytes(&mut buffer[20..28]);
+        self.metallic.write_bytes(&mut buffer[28..32]);
+        let emission: [f32; 4] = [
+            self.emission.r_linear(),
+            self.emission.g_linear(),
+            self.emission.b_linear(),
+            self.emission.a(),
+        ];
+        buffer[32..48].copy_from_slice(emission.as_bytes());
+        for (face, slot) in self
+            .faces
+            .to_array()
+            .iter()
+            .zip(buffer[48..60].chunks_exact_mut(2))
+        {
+            face.or(self.diffuse).write_bytes(slot);
+        }
+        self.metallic_roughness.write_bytes(&mut buffer[60..62]);
     }
     fn byte_len(&self) -> usize {
         MATERIAL_DATA_SIZE
     }
 }
 
+impl Default for Material {
+    fn default() -> Self {
+        Material {
+            name: "".into(),
+            scale: 1.0,
+            diffuse: None,
+            faces: FaceTextures::default(),
+            normal: None,
+            reflectivity: 0.0,
+            ior: 0.0,
+            roughness: 0.8,
+            metallic: 0.0,
+            metallic_roughness: None,
+            transparency: Transparency::Opaque,
+            emission: Color::BLACK,
+        }
+    }
+}
+
 impl Default for ColoredMaterial {
     // Default colored material is just plain color
     fn default() -> Self {
@@ -56,10 +199,10 @@ impl Default for ColoredMaterial {
             material: Material {
                 name: "PlainColor".into(),
                 scale: 0.0,
-                diffuse: None,
-                normal: None,
+                ..Default::default()
             },
             color_palette: [Color::BLACK; 256],
+            emission_palette: [0.0; 256],
         }
     }
 }
@@ -80,7 +223,11 @@ impl Bytes for ColoredMaterial {
     fn write_bytes(&self, buffer: &mut [u8]) {
         self.material
             .write_bytes(&mut buffer[0..MATERIAL_DATA_SIZE]);
-        buffer[MATERIAL_DATA_SIZE..].copy_from_slice(self.color_palette.as_bytes());
+        let emission_palette_start = MATERIAL_DATA_SIZE + COLOR_PALETTE_SIZE;
+        buffer[MATERIAL_DATA_SIZE..emission_palette_start]
+            .copy_from_slice(self.color_palette.as_bytes());
+        buffer[emission_palette_start..COLORED_MATERIAL_DATA_SIZE]
+            .copy_from_slice(self.emission_palette.as_bytes());
     }
     fn byte_len(&self) -> usize {
         COLORED_MATERIAL_DATA_SIZE
@@ -96,7 +243,13 @@ impl MaterialPalette {
             staging_buffer: None,
         }
     }
+    /// Panics when the palette already holds the 1023 materials regular voxels can refer to
     pub fn add_material(&mut self, material: Material) -> Voxel {
+        assert!(
+            self.materials.len() < crate::REGULAR_ID_MASK as usize,
+            "Regular voxels have at most {} materials",
+            crate::REGULAR_ID_MASK
+        );
         self.materials.push(material);
         // 0 was reserved for air
         Voxel::new(self.materials.len() as u16)
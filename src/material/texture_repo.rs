@@ -1,4 +1,5 @@
 use bevy::core::Bytes;
+use bevy::render::color::Color;
 use bevy::render::texture::Extent3d;
 use image::{DynamicImage, GenericImageView};
 use std::collections::hash_map;
@@ -10,9 +11,46 @@ pub struct TextureRepo {
     height: u32,
     pub(crate) textures: hash_map::HashMap<TextureRepoHandle, DynamicImage>,
     length: u16,
+    /// Animation of every layer of the texture array
+    pub(crate) frames: Vec<TextureFrame>,
+    pub(crate) frames_changed: bool,
 }
 
-#[derive(Copy, Clone, Eq, Hash, PartialEq)]
+/// A frame of an animated texture
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AnimationFrame {
+    /// Index of the frame in the vertical strip of frames, from the top
+    pub index: u32,
+    /// Time the frame is shown for, in seconds
+    pub duration: f32,
+}
+
+/// Animation of a layer of the texture array, as read by the shader.
+/// Only the first layer of an animation holds the frame count and the period.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TextureFrame {
+    pub frame_count: u32,
+    /// Duration of the whole animation
+    pub period: f32,
+    /// Time since the start of the animation at which the frame of this layer ends
+    pub frame_end: f32,
+}
+
+pub(crate) const TEXTURE_FRAME_SIZE: usize = 16;
+
+impl Bytes for TextureFrame {
+    fn write_bytes(&self, buffer: &mut [u8]) {
+        self.frame_count.write_bytes(&mut buffer[0..4]);
+        self.period.write_bytes(&mut buffer[4..8]);
+        self.frame_end.write_bytes(&mut buffer[8..12]);
+    }
+
+    fn byte_len(&self) -> usize {
+        TEXTURE_FRAME_SIZE
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, Hash, PartialEq)]
 pub struct TextureRepoHandle(NonZeroU16);
 
 impl TextureRepoHandle {
@@ -38,6 +76,8 @@ impl TextureRepo {
             height,
             textures: hash_map::HashMap::new(),
             length: 0,
+            frames: Vec::new(),
+            frames_changed: true,
         }
     }
     pub fn drain(&mut self) -> impl Iterator<Item = (TextureRepoHandle, DynamicImage)> + '_ {
@@ -48,11 +88,102 @@ impl TextureRepo {
     }
     pub fn load<'a, P: AsRef<Path>>(&mut self, path: P) -> TextureRepoHandle {
         let image = image::open(path).unwrap();
+        self.insert(image)
+    }
+    /// Loads a color texture multiplied by `tint`, like the grayscale grass and foliage
+    /// textures of Minecraft, which are tinted by biome. Unlike the color palette of a
+    /// [ColoredMaterial](super::ColoredMaterial), this only tints the faces using the texture.
+    pub fn load_tinted<'a, P: AsRef<Path>>(&mut self, path: P, tint: Color) -> TextureRepoHandle {
+        let mut image = image::open(path).unwrap().into_rgba8();
+        // Multiplying the sRGB encoded values is close to multiplying the linear colors
+        let tint = [tint.r(), tint.g(), tint.b(), tint.a()];
+        for pixel in image.pixels_mut() {
+            for (channel, factor) in pixel.0.iter_mut().zip(tint.iter()) {
+                *channel = (*channel as f32 * factor).round() as u8;
+            }
+        }
+        self.insert(DynamicImage::ImageRgba8(image))
+    }
+    /// Loads an animated texture from a vertical strip of frames of the size of the repo,
+    /// like the animated textures of Minecraft resource packs.
+    /// The frames are shown in order, each for `frame_duration` seconds.
+    pub fn load_animated<'a, P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        frame_duration: f32,
+    ) -> TextureRepoHandle {
+        let image = image::open(path).unwrap();
+        assert_eq!(image.height() % self.height, 0);
+        let frames: Vec<_> = (0..image.height() / self.height)
+            .map(|index| AnimationFrame {
+                index,
+                duration: frame_duration,
+            })
+            .collect();
+        self.insert_animated(image, &frames)
+    }
+    /// Loads an animated texture from a vertical strip of frames of the size of the repo,
+    /// showing the frames in the order and for the durations of `frames`.
+    /// Frames may be repeated.
+    pub fn load_animated_frames<'a, P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        frames: &[AnimationFrame],
+    ) -> TextureRepoHandle {
+        let image = image::open(path).unwrap();
+        self.insert_animated(image, frames)
+    }
+    /// Stores each frame of a vertical strip of frames in its own layer, after the layer of
+    /// the first frame. The shader picks the layer from the time, so the texture is never
+    /// uploaded again.
+    pub fn insert_animated(
+        &mut self,
+        image: DynamicImage,
+        frames: &[AnimationFrame],
+    ) -> TextureRepoHandle {
+        assert!(!frames.is_empty(), "Animated textures need at least one frame");
+        assert_eq!(image.width(), self.width);
+        let period: f32 = frames.iter().map(|frame| frame.duration).sum();
+        assert!(
+            frames.len() == 1 || period > 0.0,
+            "Animated textures need a positive period"
+        );
+        let mut first = None;
+        let mut frame_end = 0.0;
+        for frame in frames {
+            assert!(
+                (frame.index + 1) * self.height <= image.height(),
+                "Missing animation frame {}",
+                frame.index
+            );
+            let handle = self.insert(image.crop_imm(
+                0,
+                frame.index * self.height,
+                self.width,
+                self.height,
+            ));
+            frame_end += frame.duration;
+            self.frames[handle.get() as usize - 1] = TextureFrame {
+                frame_count: if first.is_none() { frames.len() as u32 } else { 1 },
+                period,
+                frame_end,
+            };
+            first.get_or_insert(handle);
+        }
+        first.unwrap()
+    }
+    fn insert(&mut self, image: DynamicImage) -> TextureRepoHandle {
         assert_eq!(image.width(), self.width);
         assert_eq!(image.height(), self.height);
         self.length += 1;
         let handle = TextureRepoHandle(unsafe { NonZeroU16::new_unchecked(self.length) });
         self.textures.insert(handle, image);
+        self.frames.push(TextureFrame {
+            frame_count: 1,
+            period: 0.0,
+            frame_end: 0.0,
+        });
+        self.frames_changed = true;
         handle
     }
     pub fn set<'a, P: AsRef<Path>>(&mut self, handle: TextureRepoHandle, path: P) {
@@ -61,6 +192,10 @@ impl TextureRepo {
         assert_eq!(image.height(), self.height);
         self.textures.insert(handle, image);
     }
+    /// Animation of every layer, indexed by handle minus one
+    pub fn frames(&self) -> &[TextureFrame] {
+        &self.frames
+    }
     pub fn get_extent(&self) -> Extent3d {
         Extent3d {
             width: self.width,
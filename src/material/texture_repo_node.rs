@@ -1,24 +1,33 @@
-use crate::material::texture_repo::TextureRepo;
+use crate::material::texture_repo::{TextureRepo, TEXTURE_FRAME_SIZE};
+
+use bevy::core::Bytes;
 
 use bevy::prelude::*;
 
 use bevy::render::render_graph::Node;
 use bevy::render::render_graph::{CommandQueue, ResourceSlots};
 use bevy::render::renderer::{
-    BufferInfo, BufferUsage, RenderContext, RenderResourceBinding, RenderResourceBindings,
-    SamplerId, TextureId,
+    BufferId, BufferInfo, BufferUsage, RenderContext, RenderResourceBinding,
+    RenderResourceBindings, SamplerId, TextureId,
 };
 use bevy::render::texture::{
     AddressMode, Extent3d, FilterMode, SamplerDescriptor, TextureDescriptor, TextureDimension,
-    TextureUsage,
+    TextureFormat, TextureUsage,
 };
 
+/// Format of the texture array. The texels are stored as they are in the files: unlike an
+/// sRGB format, this keeps the data of normal and metallic-roughness maps intact, and the
+/// shader decodes the sRGB colors of diffuse textures itself.
+const TEXTURE_REPO_FORMAT: TextureFormat = TextureFormat::Bgra8Unorm;
+
 #[derive(Debug)]
 pub struct TextureRepoNode {
     command_queue: CommandQueue,
     texture: Option<TextureId>,
     sampler: Option<SamplerId>,
     size: Extent3d,
+    /// Animations of the layers of the texture, bound as `TextureFrames`
+    frames_buffer: Option<BufferId>,
 }
 
 impl TextureRepoNode {
@@ -32,6 +41,7 @@ impl TextureRepoNode {
                 height: 0,
                 depth: 0,
             },
+            frames_buffer: None,
         }
     }
 }
@@ -88,7 +98,7 @@ impl Node for TextureRepoNode {
                     mip_level_count: 1,
                     sample_count: 1,
                     dimension: TextureDimension::D2,
-                    format: Default::default(),
+                    format: TEXTURE_REPO_FORMAT,
                     usage: TextureUsage::SAMPLED,
                 });
 
@@ -109,7 +119,7 @@ impl Node for TextureRepoNode {
                     mip_level_count: 1,
                     sample_count: 1,
                     dimension: TextureDimension::D2,
-                    format: Default::default(),
+                    format: TEXTURE_REPO_FORMAT,
                     usage: TextureUsage::COPY_DST | TextureUsage::SAMPLED,
                 });
             if let Some(old_texture) = self.texture {
@@ -135,6 +145,44 @@ impl Node for TextureRepoNode {
             render_resource_bindings
                 .set("TextureRepo", RenderResourceBinding::Texture(new_texture));
         }
+        if repo.frames_changed {
+            // Rarely changes, only when textures are loaded
+            repo.frames_changed = false;
+            // Storage buffers can't be empty
+            let size = TEXTURE_FRAME_SIZE * repo.frames.len().max(1);
+            let frames_buffer = render_context.resources().create_buffer(BufferInfo {
+                size,
+                buffer_usage: BufferUsage::STORAGE,
+                mapped_at_creation: true,
+            });
+            render_context.resources().write_mapped_buffer(
+                frames_buffer,
+                0..(size as u64),
+                &mut |data: &mut [u8], _renderer| {
+                    for (frame, slot) in repo
+                        .frames
+                        .iter()
+                        .zip(data.chunks_exact_mut(TEXTURE_FRAME_SIZE))
+                    {
+                        frame.write_bytes(slot);
+                    }
+                },
+            );
+            render_context.resources().unmap_buffer(frames_buffer);
+            if let Some(old_buffer) = self.frames_buffer.replace(frames_buffer) {
+                render_context.resources().remove_buffer(old_buffer);
+            }
+            let mut render_resource_bindings =
+                resources.get_mut::<RenderResourceBindings>().unwrap();
+            render_resource_bindings.set(
+                "TextureFrames",
+                RenderResourceBinding::Buffer {
+                    buffer: frames_buffer,
+                    range: 0..size as u64,
+                    dynamic_index: None,
+                },
+            );
+        }
         // Copy new textures
         let image_size: usize =
             self.size.width as usize * self.size.height as usize * std::mem::size_of::<u32>();
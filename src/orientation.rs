@@ -0,0 +1,133 @@
+/// A face of a voxel, named after the Minecraft directions.
+/// The discriminants are the face indices used by the shader.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Face {
+    /// +X
+    East = 0,
+    /// -X
+    West = 1,
+    /// +Y
+    Top = 2,
+    /// -Y
+    Bottom = 3,
+    /// +Z
+    South = 4,
+    /// -Z
+    North = 5,
+}
+
+const FACES: [Face; 6] = [
+    Face::East,
+    Face::West,
+    Face::Top,
+    Face::Bottom,
+    Face::South,
+    Face::North,
+];
+
+impl Face {
+    pub fn normal(self) -> [i32; 3] {
+        match self {
+            Face::East => [1, 0, 0],
+            Face::West => [-1, 0, 0],
+            Face::Top => [0, 1, 0],
+            Face::Bottom => [0, -1, 0],
+            Face::South => [0, 0, 1],
+            Face::North => [0, 0, -1],
+        }
+    }
+    fn from_normal(normal: [i32; 3]) -> Face {
+        *FACES.iter().find(|face| face.normal() == normal).unwrap()
+    }
+}
+
+/// Axis of pillar-like blocks such as logs
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Axis {
+    X,
+    Y,
+    Z,
+}
+
+/// One of the 24 rotations of a voxel, stored in bits 10 - 14 of regular voxels.
+///
+/// The rotation turns the top face of the voxel toward `up`, then turns the voxel
+/// `turns` quarter turns around `up`. Without turns, the north face of the voxel faces
+/// north, or down when `up` is south or north.
+/// The default orientation leaves the voxel unrotated.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct Orientation(u8);
+
+impl Orientation {
+    pub const COUNT: u8 = 24;
+
+    pub fn new(up: Face, turns: u8) -> Self {
+        // Encoded so that the unrotated orientation is 0
+        let up_code = (up as u8 + 4) % 6;
+        Orientation(up_code * 4 + turns % 4)
+    }
+    /// The orientation stored in a voxel. Panics when `bits` is not below [Orientation::COUNT].
+    pub fn from_bits(bits: u8) -> Self {
+        assert!(bits < Self::COUNT, "Invalid orientation {}", bits);
+        Orientation(bits)
+    }
+    pub fn bits(self) -> u8 {
+        self.0
+    }
+    pub fn up(self) -> Face {
+        FACES[((self.0 / 4 + 2) % 6) as usize]
+    }
+    pub fn turns(self) -> u8 {
+        self.0 % 4
+    }
+
+    /// Orientation of a pillar whose ends point along the axis
+    pub fn from_axis(axis: Axis) -> Self {
+        match axis {
+            Axis::X => Orientation::new(Face::East, 0),
+            Axis::Y => Orientation::default(),
+            Axis::Z => Orientation::new(Face::South, 0),
+        }
+    }
+
+    /// Orientation turning the north face of the voxel toward `facing`,
+    /// keeping the top face up whenever possible
+    pub fn facing(facing: Face) -> Self {
+        (0..Self::COUNT)
+            .map(Orientation)
+            .find(|orientation| orientation.rotate(Face::North) == facing)
+            .unwrap()
+    }
+
+    /// Images of the X, Y and Z axes of the voxel
+    fn basis(self) -> [[i32; 3]; 3] {
+        let up = self.up().normal();
+        let mut forward = if up[2] != 0 { [0, -1, 0] } else { [0, 0, -1] };
+        for _ in 0..self.turns() {
+            forward = cross(up, forward);
+        }
+        let back = [-forward[0], -forward[1], -forward[2]];
+        [cross(up, back), up, back]
+    }
+
+    /// The face a face of the voxel turns into
+    pub fn rotate(self, face: Face) -> Face {
+        let basis = self.basis();
+        let normal = face.normal();
+        let mut rotated = [0; 3];
+        for (axis, image) in basis.iter().enumerate() {
+            for (component, value) in rotated.iter_mut().enumerate() {
+                *value += image[component] * normal[axis];
+            }
+        }
+        Face::from_normal(rotated)
+    }
+}
+
+fn cross(a: [i32; 3], b: [i32; 3]) -> [i32; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
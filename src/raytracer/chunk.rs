@@ -23,6 +23,13 @@ impl Chunk {
             octree,
         }
     }
+
+    /// Serializes the octree nodes in the layout used by the GPU buffer.
+    pub fn node_data(&self) -> Vec<u8> {
+        let mut data = vec![0; self.octree.total_data_size()];
+        self.octree.copy_into_slice(&mut data);
+        data
+    }
 }
 
 pub struct ChunkState {
@@ -30,9 +37,10 @@ pub struct ChunkState {
     pub(crate) staging_buffer: Option<BufferId>,
 }
 
+/// A chunk drawn by the ray passes of the cameras. Its bindings are held by its
+/// `RenderPipelines`, but it has no `Draw`: the ray passes draw it themselves.
 #[derive(Bundle)]
 pub struct ChunkBundle {
-    pub draw: Draw,
     pub visible: Visible,
     pub ray_pass: RayPass,
     pub chunk: Handle<Chunk>,
@@ -46,7 +54,6 @@ impl ChunkBundle {
     pub fn new(chunk: Handle<Chunk>) -> Self {
         ChunkBundle {
             chunk,
-            draw: Default::default(),
             visible: Visible {
                 is_visible: true,
                 is_transparent: false,
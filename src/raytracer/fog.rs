@@ -0,0 +1,76 @@
+use bevy::core::{AsBytes, Bytes};
+use bevy::render::color::Color;
+
+/// Fog hiding distant voxels, by the distance between the camera and the surfaces hit.
+/// Uploaded with the `RayTracerSettings` uniform. There is no fog by default, insert a `Fog`
+/// resource with another [FogMode] to enable it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fog {
+    pub mode: FogMode,
+    /// Color the voxels fade to. The alpha is the opacity of the fog: with a transparent
+    /// color, distant voxels fade out instead, revealing whatever is drawn behind the
+    /// chunks, like the sky of `bevy_sky`, so distant terrain blends into the horizon.
+    ///
+    /// It is not derived from the [Sky](crate::lights::Sky): primary rays leaving the chunks
+    /// are discarded, so what the camera sees behind the voxels is whatever is drawn there,
+    /// and only a transparent fog fades into it exactly. The `Sky` is only seen by secondary
+    /// rays. Pick an opaque color yourself to hide distant voxels behind a solid haze.
+    pub color: Color,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FogMode {
+    None,
+    /// Fog growing linearly from nothing at `start` to full at `end`
+    Linear { start: f32, end: f32 },
+    /// Fog of uniform density, hiding a fraction `1 - exp(-density * distance)` of the surfaces
+    Exponential { density: f32 },
+    /// Like [FogMode::Exponential], with a sharper transition: `1 - exp(-(density * distance)²)`
+    ExponentialSquared { density: f32 },
+    /// Fog of density `density` at `height`, thinning out by a factor e
+    /// every `1 / falloff` units above it, and thickening below it
+    Height {
+        density: f32,
+        falloff: f32,
+        height: f32,
+    },
+}
+
+impl Default for Fog {
+    fn default() -> Self {
+        Fog {
+            mode: FogMode::None,
+            color: Color::rgba(0.0, 0.0, 0.0, 0.0),
+        }
+    }
+}
+
+pub(crate) const FOG_SIZE: usize = 32;
+
+impl Bytes for Fog {
+    fn write_bytes(&self, buffer: &mut [u8]) {
+        let color: [f32; 4] = [
+            self.color.r_linear(),
+            self.color.g_linear(),
+            self.color.b_linear(),
+            self.color.a(),
+        ];
+        buffer[0..16].copy_from_slice(color.as_bytes());
+        let (mode, params): (u32, [f32; 3]) = match self.mode {
+            FogMode::None => (0, [0.0; 3]),
+            FogMode::Linear { start, end } => (1, [start, end, 0.0]),
+            FogMode::Exponential { density } => (2, [density, 0.0, 0.0]),
+            FogMode::ExponentialSquared { density } => (3, [density, 0.0, 0.0]),
+            FogMode::Height {
+                density,
+                falloff,
+                height,
+            } => (4, [density, falloff, height]),
+        };
+        mode.write_bytes(&mut buffer[16..20]);
+        buffer[20..32].copy_from_slice(params.as_bytes());
+    }
+    fn byte_len(&self) -> usize {
+        FOG_SIZE
+    }
+}
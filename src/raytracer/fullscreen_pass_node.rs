@@ -0,0 +1,245 @@
+use std::borrow::Cow;
+use bevy::prelude::*;
+use bevy::reflect::TypeUuid;
+use bevy::render::mesh::{INDEX_BUFFER_ASSET_INDEX, VERTEX_ATTRIBUTE_BUFFER_ID};
+use bevy::render::pass::{PassDescriptor, RenderPassColorAttachmentDescriptor, TextureAttachment};
+use bevy::render::pipeline::{
+    BindGroupDescriptor, IndexFormat, PipelineCompiler, PipelineDescriptor,
+    PipelineSpecialization, PrimitiveTopology,
+};
+use bevy::render::render_graph::{Node, ResourceSlotInfo, ResourceSlots};
+use bevy::render::renderer::{
+    BindGroup, RenderContext, RenderResourceBinding, RenderResourceBindings,
+    RenderResourceType, SamplerId,
+};
+use bevy::render::texture::{AddressMode, FilterMode, SamplerDescriptor};
+use bevy::window::WindowId;
+
+pub const FULLSCREEN_QUAD_HANDLE: HandleUntyped =
+    HandleUntyped::weak_from_u64(Mesh::TYPE_UUID, 0x786f7ab62875ebbf);
+
+/// Draws the fullscreen quad with a pipeline, sampling the textures of its input slots.
+///
+/// Unlike a `PassNode`, which replays draw commands recorded before the render graph runs,
+/// the bind groups are built when the node runs. Each instance therefore reads the textures
+/// it was given this very frame, which lets every camera have its own post processing passes.
+///
+/// Textures are bound by the name of their input slot, with a sampler named
+/// `{name}Sampler`. Other bindings, like uniform buffers, are taken from the
+/// `RenderResourceBindings` resource by name.
+#[derive(Debug)]
+pub struct FullscreenPassNode {
+    pipeline: Handle<PipelineDescriptor>,
+    descriptor: PassDescriptor,
+    inputs: Vec<ResourceSlotInfo>,
+    /// Filters of the sampled texture inputs, following the color attachment inputs
+    filters: Vec<FilterMode>,
+    samplers: Vec<(FilterMode, SamplerId)>,
+    bindings: RenderResourceBindings,
+    /// Fraction of the window drawn to, as `[x, y, width, height]`
+    viewport: Option<(WindowId, [f32; 4])>,
+    specialized_pipeline: Option<(Handle<PipelineDescriptor>, Vec<BindGroupDescriptor>)>,
+}
+
+impl FullscreenPassNode {
+    /// Color attachments read from input slots are given as `TextureAttachment::Input`
+    pub fn new(
+        pipeline: Handle<PipelineDescriptor>,
+        color_attachments: Vec<RenderPassColorAttachmentDescriptor>,
+    ) -> Self {
+        let inputs = color_attachments
+            .iter()
+            .filter_map(|attachment| match &attachment.attachment {
+                TextureAttachment::Input(name) => Some(ResourceSlotInfo {
+                    name: Cow::Owned(name.clone()),
+                    resource_type: RenderResourceType::Texture,
+                }),
+                _ => None,
+            })
+            .collect();
+        FullscreenPassNode {
+            pipeline,
+            descriptor: PassDescriptor {
+                color_attachments,
+                depth_stencil_attachment: None,
+                sample_count: 1,
+            },
+            inputs,
+            filters: Vec::new(),
+            samplers: Vec::new(),
+            bindings: Default::default(),
+            viewport: None,
+            specialized_pipeline: None,
+        }
+    }
+
+    /// Adds an input slot for a texture sampled by the shader under the name `name`
+    pub fn add_texture(&mut self, name: &'static str, filter: FilterMode) {
+        self.inputs.push(ResourceSlotInfo {
+            name: Cow::Borrowed(name),
+            resource_type: RenderResourceType::Texture,
+        });
+        self.filters.push(filter);
+    }
+
+    /// Restricts the drawing to a fraction `[x, y, width, height]` of a window, from its top
+    /// left corner, when the color attachment is its swap chain. The textures are still
+    /// sampled whole.
+    pub fn set_viewport(&mut self, window: WindowId, viewport: [f32; 4]) {
+        self.viewport = Some((window, viewport));
+    }
+
+    fn sampler(&mut self, render_context: &mut dyn RenderContext, filter: FilterMode) -> SamplerId {
+        if let Some(&(_, sampler)) = self.samplers.iter().find(|(f, _)| *f == filter) {
+            return sampler;
+        }
+        let sampler = render_context
+            .resources()
+            .create_sampler(&SamplerDescriptor {
+                address_mode_u: AddressMode::ClampToEdge,
+                address_mode_v: AddressMode::ClampToEdge,
+                address_mode_w: AddressMode::ClampToEdge,
+                mag_filter: filter,
+                min_filter: filter,
+                mipmap_filter: FilterMode::Nearest,
+                lod_min_clamp: 0.0,
+                lod_max_clamp: std::f32::MAX,
+                compare_function: None,
+                anisotropy_clamp: None,
+                border_color: None,
+            });
+        self.samplers.push((filter, sampler));
+        sampler
+    }
+}
+
+impl Node for FullscreenPassNode {
+    fn input(&self) -> &[ResourceSlotInfo] {
+        &self.inputs
+    }
+
+    fn update(
+        &mut self,
+        _world: &World,
+        resources: &Resources,
+        render_context: &mut dyn RenderContext,
+        input: &ResourceSlots,
+        _output: &mut ResourceSlots,
+    ) {
+        let quad_handle: Handle<Mesh> = FULLSCREEN_QUAD_HANDLE.typed();
+        if self.specialized_pipeline.is_none() {
+            let meshes = resources.get::<Assets<Mesh>>().unwrap();
+            let quad = meshes.get(&quad_handle).unwrap();
+            let mut pipeline_compiler = resources.get_mut::<PipelineCompiler>().unwrap();
+            let mut pipelines = resources.get_mut::<Assets<PipelineDescriptor>>().unwrap();
+            let mut shaders = resources.get_mut::<Assets<Shader>>().unwrap();
+            let specialized_pipeline = pipeline_compiler.compile_pipeline(
+                render_context.resources(),
+                &mut pipelines,
+                &mut shaders,
+                &self.pipeline,
+                &PipelineSpecialization {
+                    primitive_topology: PrimitiveTopology::TriangleStrip,
+                    strip_index_format: Some(IndexFormat::Uint16),
+                    vertex_buffer_layout: quad.get_vertex_buffer_layout(),
+                    ..Default::default()
+                },
+            );
+            let bind_groups = pipelines
+                .get(&specialized_pipeline)
+                .unwrap()
+                .get_layout()
+                .unwrap()
+                .bind_groups
+                .clone();
+            self.specialized_pipeline = Some((specialized_pipeline, bind_groups));
+        }
+
+        let attachment_count = self.inputs.len() - self.filters.len();
+        for (i, filter) in self.filters.clone().into_iter().enumerate() {
+            let slot = attachment_count + i;
+            let name = self.inputs[slot].name.to_string();
+            let sampler = self.sampler(render_context, filter);
+            let texture = input.get(slot).unwrap().get_texture().unwrap();
+            self.bindings.set(&name, RenderResourceBinding::Texture(texture));
+            self.bindings
+                .set(&format!("{}Sampler", name), RenderResourceBinding::Sampler(sampler));
+        }
+
+        let render_resource_bindings = resources.get::<RenderResourceBindings>().unwrap();
+        let (pipeline, bind_group_descriptors) = self.specialized_pipeline.as_ref().unwrap();
+        let mut bind_groups = Vec::with_capacity(bind_group_descriptors.len());
+        for descriptor in bind_group_descriptors.iter() {
+            let mut bind_group = BindGroup::build();
+            for binding in descriptor.bindings.iter() {
+                let resource = self
+                    .bindings
+                    .get(&binding.name)
+                    .or_else(|| render_resource_bindings.get(&binding.name));
+                match resource {
+                    Some(resource) => {
+                        bind_group = bind_group.add_binding(binding.index, resource.clone());
+                    }
+                    // Not uploaded yet
+                    None => return,
+                }
+            }
+            let bind_group = bind_group.finish();
+            if !render_context
+                .resources()
+                .bind_group_exists(descriptor.id, bind_group.id)
+            {
+                render_context
+                    .resources()
+                    .create_bind_group(descriptor.id, &bind_group);
+            }
+            bind_groups.push((descriptor.index, descriptor.id, bind_group.id));
+        }
+
+        let mut descriptor = self.descriptor.clone();
+        for attachment in descriptor.color_attachments.iter_mut() {
+            if let TextureAttachment::Input(name) = &attachment.attachment {
+                let texture = input.get(name.as_str()).unwrap().get_texture().unwrap();
+                attachment.attachment = TextureAttachment::Id(texture);
+            }
+        }
+        let viewport = self.viewport.map(|(window, [x, y, width, height])| {
+            let windows = resources.get::<Windows>().unwrap();
+            let window = windows.get(window).unwrap();
+            (
+                x * window.physical_width() as f32,
+                y * window.physical_height() as f32,
+                width * window.physical_width() as f32,
+                height * window.physical_height() as f32,
+            )
+        });
+        let vertex_buffer = render_context
+            .resources()
+            .get_asset_resource(&quad_handle, VERTEX_ATTRIBUTE_BUFFER_ID)
+            .and_then(|resource| resource.get_buffer());
+        let index_buffer = render_context
+            .resources()
+            .get_asset_resource(&quad_handle, INDEX_BUFFER_ASSET_INDEX)
+            .and_then(|resource| resource.get_buffer());
+        let (vertex_buffer, index_buffer) = match (vertex_buffer, index_buffer) {
+            (Some(vertex_buffer), Some(index_buffer)) => (vertex_buffer, index_buffer),
+            _ => return,
+        };
+        render_context.begin_pass(
+            &descriptor,
+            &render_resource_bindings,
+            &mut |render_pass| {
+                render_pass.set_pipeline(pipeline);
+                if let Some((x, y, width, height)) = viewport {
+                    render_pass.set_viewport(x, y, width, height, 0.0, 1.0);
+                }
+                render_pass.set_vertex_buffer(0, vertex_buffer, 0);
+                render_pass.set_index_buffer(index_buffer, 0, IndexFormat::Uint16);
+                for &(index, descriptor_id, bind_group_id) in bind_groups.iter() {
+                    render_pass.set_bind_group(index, descriptor_id, bind_group_id, None);
+                }
+                render_pass.draw_indexed(0..4, 0, 0..1);
+            },
+        );
+    }
+}
@@ -0,0 +1,138 @@
+use crate::raytracer::readback_node::{TextureReadback, TextureReadbackNode};
+use crate::raytracer::target::{RayPassTarget, TargetTextureNode};
+use crate::raytracer::{add_ray_pass_camera, camera_node_name, node, OctreeRayTracerPlugin};
+use bevy::ecs::DynamicBundle;
+use bevy::prelude::*;
+use bevy::render::camera::{Camera, CameraProjection};
+use bevy::render::render_graph::base::{camera::CAMERA_3D, BaseRenderGraphConfig};
+use bevy::render::render_graph::RenderGraph;
+use bevy::render::RenderPlugin;
+use bevy::window::WindowPlugin;
+use image::RgbaImage;
+use std::fmt::{Display, Formatter};
+
+/// Frames rendered before the image is read back, so that chunks, materials and textures
+/// are uploaded, and global illumination and temporal anti-aliasing settle
+const WARM_UP_FRAMES: usize = 16;
+/// Frames rendered after the request before giving up on the image. It is usually read back
+/// on the second one.
+const MAX_READBACK_FRAMES: usize = 8;
+
+pub const READBACK_NODE: &str = "ray_tracer_readback";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenderToImageError {
+    /// The image wasn't read back within a few frames, like when the render graph failed
+    /// to run
+    NotReadBack,
+}
+
+impl Display for RenderToImageError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RenderToImageError::NotReadBack => f.write_str("The image wasn't read back"),
+        }
+    }
+}
+
+impl std::error::Error for RenderToImageError {}
+
+/// A camera bundle [render_to_image] can render with
+pub trait HeadlessCamera: DynamicBundle + Send + Sync + 'static {
+    /// Names the camera after the 3d camera, and sizes its projection for an image of
+    /// `width` by `height` pixels, like when the window of a camera is resized
+    fn set_size(&mut self, width: u32, height: u32);
+}
+
+fn set_camera_size<P: CameraProjection>(
+    camera: &mut Camera,
+    projection: &mut P,
+    width: u32,
+    height: u32,
+) {
+    camera.name = Some(CAMERA_3D.to_string());
+    projection.update(width as f32, height as f32);
+    camera.projection_matrix = projection.get_projection_matrix();
+}
+
+impl HeadlessCamera for PerspectiveCameraBundle {
+    fn set_size(&mut self, width: u32, height: u32) {
+        set_camera_size(&mut self.camera, &mut self.perspective_projection, width, height);
+    }
+}
+
+impl HeadlessCamera for OrthographicCameraBundle {
+    fn set_size(&mut self, width: u32, height: u32) {
+        set_camera_size(&mut self.camera, &mut self.orthographic_projection, width, height);
+    }
+}
+
+/// Renders voxels without any window, for thumbnails or batch previews.
+///
+/// `scene` is a startup system spawning the chunks and setting up the palettes, textures
+/// and lights, like the setup of an app with a window. The camera, perspective or
+/// orthographic, is named after the 3d camera, and its projection is sized for the image.
+pub fn render_to_image<S: System<In = (), Out = ()>, C: HeadlessCamera>(
+    scene: S,
+    mut camera: C,
+    size: (u32, u32),
+) -> Result<RgbaImage, RenderToImageError> {
+    let (width, height) = size;
+    let target = RayPassTarget::Texture { width, height };
+    camera.set_size(width, height);
+
+    let mut app = App::build();
+    app.add_plugin(bevy::reflect::ReflectPlugin::default())
+        .add_plugin(bevy::core::CorePlugin::default())
+        .add_plugin(bevy::transform::TransformPlugin::default())
+        .add_plugin(WindowPlugin {
+            add_primary_window: false,
+            exit_on_close: false,
+        })
+        .add_plugin(bevy::asset::AssetPlugin::default())
+        .add_plugin(RenderPlugin {
+            base_render_graph_config: Some(BaseRenderGraphConfig {
+                add_2d_camera: false,
+                add_3d_camera: true,
+                add_main_depth_texture: false,
+                add_main_pass: false,
+                connect_main_pass_to_swapchain: false,
+                connect_main_pass_to_main_depth_texture: false,
+            }),
+        })
+        .add_plugin(bevy::wgpu::WgpuPlugin::default())
+        .add_plugin(OctreeRayTracerPlugin::default())
+        .add_startup_system(scene);
+    app.world_mut().spawn(camera);
+
+    add_ray_pass_camera(app.resources(), CAMERA_3D, target);
+    let readback = TextureReadback::default();
+    {
+        let mut render_graph = app.resources().get_mut::<RenderGraph>().unwrap();
+        render_graph.add_node(READBACK_NODE, TextureReadbackNode::new(target, readback.clone()));
+        render_graph
+            .add_slot_edge(
+                camera_node_name(CAMERA_3D, node::OUTPUT_TEXTURE),
+                TargetTextureNode::OUT_TEXTURE,
+                READBACK_NODE,
+                TextureReadbackNode::IN_TEXTURE,
+            )
+            .unwrap();
+        render_graph
+            .add_node_edge(camera_node_name(CAMERA_3D, node::TONEMAPPING_PASS), READBACK_NODE)
+            .unwrap();
+    }
+
+    let mut app = app.app;
+    for _ in 0..WARM_UP_FRAMES {
+        app.update();
+    }
+    readback.request();
+    for _ in 0..MAX_READBACK_FRAMES {
+        app.update();
+        if let Some(image) = readback.take() {
+            return Ok(image);
+        }
+    }
+    Err(RenderToImageError::NotReadBack)
+}
@@ -1,7 +1,8 @@
 use crate::lights::node::LightsNode;
-use crate::lights::{AmbientLight, SunLight};
+use crate::lights::{AmbientLight, Sky, SunLight};
 use crate::material::material_node::MaterialNode;
 
+use crate::material::texture_repo::TextureRepo;
 use crate::material::texture_repo_node::TextureRepoNode;
 use crate::material::{MaterialPalette, DEFAULT_MATERIAL_PALETTE_HANDLE};
 use crate::raytracer::chunk::Chunk;
@@ -12,7 +13,7 @@ use bevy::reflect::TypeUuid;
 
 use bevy::render::mesh::Indices;
 use bevy::render::pass::{
-    LoadOp, Operations, PassDescriptor, RenderPassColorAttachmentDescriptor,
+    LoadOp, Operations, RenderPassColorAttachmentDescriptor,
     RenderPassDepthStencilAttachmentDescriptor, TextureAttachment,
 };
 use bevy::render::pipeline::{
@@ -22,90 +23,528 @@ use bevy::render::pipeline::{
     StencilState,
 };
 use bevy::render::render_graph::base as base_render_graph;
-use bevy::render::render_graph::{PassNode, RenderGraph, WindowSwapChainNode, WindowTextureNode};
+use bevy::render::camera::ActiveCameras;
+use bevy::render::render_graph::{CameraNode, RenderGraph, WindowSwapChainNode, WindowTextureNode};
 
 use bevy::render::shader::{ShaderStage, ShaderStages};
-use bevy::render::texture::{TextureFormat, TextureDescriptor, Extent3d, TextureDimension, TextureUsage};
+use bevy::render::texture::{
+    Extent3d, FilterMode, TextureDescriptor, TextureDimension, TextureFormat, TextureUsage,
+};
 use crate::raytracer::sequencing_node::SequencingNode;
+use crate::raytracer::fog::Fog;
+use crate::raytracer::screenshot::{
+    add_screenshot_nodes, request_screenshots, save_screenshots, Screenshot, Screenshots,
+    SCREENSHOT_PIPELINE_HANDLE,
+};
+use crate::raytracer::settings::RayTracerSettings;
+use crate::raytracer::settings_node::SettingsNode;
+use crate::raytracer::fullscreen_pass_node::{FullscreenPassNode, FULLSCREEN_QUAD_HANDLE};
+use crate::raytracer::ray_pass_node::RayPassNode;
+use crate::raytracer::taa::TAA_PIPELINE_HANDLE;
+use crate::raytracer::target::{RayPassTarget, TargetTextureNode};
+use crate::raytracer::tonemapping::{Tonemapping, TonemappingNode, TONEMAPPING_PIPELINE_HANDLE};
 use bevy::window::WindowId;
 use bevy::render::renderer::RenderResourceType;
 
 pub mod chunk;
 pub mod chunk_node;
+pub mod fog;
+mod fullscreen_pass_node;
+pub mod headless;
+mod ray_pass_node;
+pub mod readback_node;
+pub mod screenshot;
 mod sequencing_node;
+pub mod settings;
+pub mod settings_node;
+pub mod taa;
+pub mod target;
+pub mod tonemapping;
+pub mod traversal;
 
 pub const RAY_PIPELINE_HANDLE: HandleUntyped =
     HandleUntyped::weak_from_u64(PipelineDescriptor::TYPE_UUID, 0x786f7ab62875ebbc);
 pub const RAY_PIPELINE_CUBE_HANDLE: HandleUntyped =
     HandleUntyped::weak_from_u64(Mesh::TYPE_UUID, 0x786f7ab62875ebbd);
 
+/// Format of the textures accumulating global illumination over frames.
+/// Alpha holds the number of accumulated frames.
+const GI_HISTORY_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+/// Format of the texture the ray pass renders into, before tone mapping
+const HDR_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+/// Format of the motion of the surfaces in screen space, for temporal anti-aliasing
+const VELOCITY_FORMAT: TextureFormat = TextureFormat::Rg16Float;
+
+/// Draws the chunks with ray tracing. Inserts a [TextureRepo] of 16x16 textures unless the
+/// app already has one, so insert it before adding the plugin to use other sizes.
 #[derive(Default)]
 pub struct OctreeRayTracerPlugin;
 
+/// A texture of the size of a target, rendered to by a pass and read by the next ones
+fn sampled_target_texture_node(target: RayPassTarget, format: TextureFormat) -> TargetTextureNode {
+    TargetTextureNode::new(
+        target,
+        TextureDescriptor {
+            size: Extent3d {
+                depth: 1,
+                width: 1,
+                height: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format,
+            usage: TextureUsage::OUTPUT_ATTACHMENT | TextureUsage::SAMPLED,
+        },
+    )
+}
+
 /// A component that indicates that an entity should be drawn in the "main pass"
 #[derive(Clone, Debug, Default, Reflect)]
 #[reflect(Component)]
 pub struct RayPass;
 
 pub mod node {
-    pub const RAY_PASS: &str = "ray_pass";
     pub const OCTREE_CHUNK_NODE: &str = "octree_chunk_node";
     pub const LIGHT_NODE: &str = "light_node";
     pub const TEXTURE_REPO: &str = "texture_repo_node";
     pub const MATERIAL_REPO: &str = "material_repo_node";
+    pub const SETTINGS_NODE: &str = "ray_tracer_settings_node";
+    pub const TONEMAPPING_NODE: &str = "tonemapping_node";
+    /// Swap chain of a window other than the primary one, suffixed by the id of the window
+    pub const SWAP_CHAIN: &str = "ray_tracer_swap_chain";
+
+    // Added for each camera by `add_ray_pass_camera`, and named by `camera_node_name`
+    pub const CAMERA: &str = "camera";
+    pub const RAY_PASS: &str = "ray_pass";
+    pub const DEPTH_TEXTURE: &str = "depth";
     pub const ALT_DEPTH_TEXTURE: &str = "alt_depth";
     pub const DEPTH_SEQUENCING_NODE: &str = "depth_sequencing";
+    pub const GI_HISTORY_TEXTURE: &str = "gi_history";
+    pub const ALT_GI_HISTORY_TEXTURE: &str = "alt_gi_history";
+    pub const GI_HISTORY_SEQUENCING_NODE: &str = "gi_history_sequencing";
+    pub const HDR_TEXTURE: &str = "hdr_texture";
+    pub const VELOCITY_TEXTURE: &str = "velocity";
+    pub const TAA_PASS: &str = "taa_pass";
+    pub const TAA_OUTPUT_TEXTURE: &str = "taa_output";
+    pub const TAA_HISTORY_TEXTURE: &str = "taa_history";
+    pub const ALT_TAA_HISTORY_TEXTURE: &str = "alt_taa_history";
+    pub const TAA_HISTORY_SEQUENCING_NODE: &str = "taa_history_sequencing";
+    pub const TONEMAPPING_PASS: &str = "tonemapping_pass";
+    /// The tone mapped image of a camera targeting a `RayPassTarget::Texture`
+    pub const OUTPUT_TEXTURE: &str = "output";
 }
 
-impl Plugin for OctreeRayTracerPlugin {
-    fn build(&self, app: &mut AppBuilder) {
+/// Name of a node added for a camera by [add_ray_pass_camera], like [node::RAY_PASS]
+pub fn camera_node_name(camera: &str, node: &str) -> String {
+    format!("{}_{}", camera, node)
+}
+
+/// Adds the render graph nodes drawing the voxels seen by a camera into a target.
+///
+/// The plugin adds them for the 3d camera and the primary window, unless the render graph
+/// was configured without a swap chain, like by [render_to_image](headless::render_to_image).
+/// Other cameras, like the ones of secondary windows, split-screen viewports, minimaps or
+/// security cameras, need to be spawned with the same name. A window must exist when its
+/// nodes are added. Cameras targeting a [RayPassTarget::Texture] output it from their
+/// [node::OUTPUT_TEXTURE] node.
+///
+/// Global illumination accumulation and temporal anti-aliasing rely on the previous frames
+/// of the 3d camera, whatever its target, so other cameras trace each frame on its own,
+/// with a ray pass pipeline specialized without the history: their rays are neither
+/// jittered nor blended with the previous frames.
+pub fn add_ray_pass_camera(resources: &Resources, camera: &str, target: RayPassTarget) {
+    let msaa = resources.get::<Msaa>().unwrap();
+    let mut render_graph = resources.get_mut::<RenderGraph>().unwrap();
+    let name = |node: &str| camera_node_name(camera, node);
+    // The camera with the history of the previous frames
+    let has_history = camera == base_render_graph::camera::CAMERA_3D;
+    let over_main_pass = has_history
+        && target == RayPassTarget::Window(WindowId::primary())
+        && render_graph
+            .get_node_state(base_render_graph::node::MAIN_DEPTH_TEXTURE)
+            .is_ok();
+
+    let mut ray_pass_node = RayPassNode::new(
+        RAY_PIPELINE_HANDLE.typed(),
+        camera,
+        vec![
+            RenderPassColorAttachmentDescriptor {
+                attachment: TextureAttachment::Input("color_attachment".to_string()),
+                resolve_target: None,
+                ops: Operations {
+                    // Composited over the target by the tone mapping pass
+                    load: LoadOp::Clear(Color::rgba(0.0, 0.0, 0.0, 0.0)),
+                    store: true,
+                },
+            },
+            RenderPassColorAttachmentDescriptor {
+                attachment: TextureAttachment::Input("history_attachment".to_string()),
+                resolve_target: None,
+                ops: Operations {
+                    // Pixels not covered by any chunk restart their accumulation
+                    load: LoadOp::Clear(Color::rgba(0.0, 0.0, 0.0, 0.0)),
+                    store: true,
+                },
+            },
+            RenderPassColorAttachmentDescriptor {
+                attachment: TextureAttachment::Input("velocity_attachment".to_string()),
+                resolve_target: None,
+                ops: Operations {
+                    load: LoadOp::Clear(Color::rgba(0.0, 0.0, 0.0, 0.0)),
+                    store: true,
+                },
+            },
+        ],
+        RenderPassDepthStencilAttachmentDescriptor {
+            attachment: TextureAttachment::Input("depth".to_string()),
+            depth_ops: Some(Operations {
+                // Only the 3d camera of the primary window is depth tested against the main pass
+                load: if over_main_pass {
+                    LoadOp::Load
+                } else {
+                    LoadOp::Clear(1.0)
+                },
+                store: true,
+            }),
+            stencil_ops: None,
+        },
+    );
+    if has_history {
+        ray_pass_node.add_texture("GIHistory", FilterMode::Nearest);
+    } else {
+        ray_pass_node.add_shader_def("WITHOUT_HISTORY");
+    }
+    render_graph.add_node(name(node::RAY_PASS), ray_pass_node);
+    for &shared_node in &[
+        base_render_graph::node::TEXTURE_COPY,
+        base_render_graph::node::SHARED_BUFFERS,
+        // So that pixels covered by UI / Mesh rendered objects will not be traced
+        base_render_graph::node::MAIN_PASS,
+        node::OCTREE_CHUNK_NODE,
+        node::MATERIAL_REPO,
+        node::TEXTURE_REPO,
+        node::LIGHT_NODE,
+        node::SETTINGS_NODE,
+    ] {
+        // The main pass is missing when rendering without a window
+        if render_graph.get_node_state(shared_node).is_ok() {
+            render_graph
+                .add_node_edge(shared_node, name(node::RAY_PASS))
+                .unwrap();
+        }
+    }
+    if camera == base_render_graph::camera::CAMERA_3D {
+        render_graph
+            .add_node_edge(base_render_graph::node::CAMERA_3D, name(node::RAY_PASS))
+            .unwrap();
+    } else {
+        resources.get_mut::<ActiveCameras>().unwrap().add(camera);
+        render_graph.add_system_node(name(node::CAMERA), CameraNode::new(camera));
+        render_graph
+            .add_node_edge(name(node::CAMERA), name(node::RAY_PASS))
+            .unwrap();
+    }
+
+    // Depth texture
+    if over_main_pass {
+        // Alt depth texture
+        render_graph.add_node(name(node::DEPTH_SEQUENCING_NODE), SequencingNode::new(2));
+        render_graph.add_node(
+            name(node::ALT_DEPTH_TEXTURE),
+            WindowTextureNode::new(
+                WindowId::primary(),
+                TextureDescriptor {
+                    size: Extent3d {
+                        depth: 1,
+                        width: 1,
+                        height: 1,
+                    },
+                    mip_level_count: 1,
+                    sample_count: msaa.samples,
+                    dimension: TextureDimension::D2,
+                    format: TextureFormat::Depth32Float, // PERF: vulkan docs recommend using 24 bit depth for better performance
+                    usage: TextureUsage::OUTPUT_ATTACHMENT,
+                },
+            ),
+        );
+        render_graph
+            .add_slot_edge(
+                base_render_graph::node::MAIN_DEPTH_TEXTURE,
+                WindowTextureNode::OUT_TEXTURE,
+                name(node::DEPTH_SEQUENCING_NODE),
+                0,
+            )
+            .unwrap();
+        render_graph
+            .add_slot_edge(
+                name(node::ALT_DEPTH_TEXTURE),
+                WindowTextureNode::OUT_TEXTURE,
+                name(node::DEPTH_SEQUENCING_NODE),
+                1,
+            )
+            .unwrap();
+        render_graph
+            .add_slot_edge(
+                name(node::DEPTH_SEQUENCING_NODE),
+                SequencingNode::OUT_TEXTURE,
+                name(node::RAY_PASS),
+                "depth",
+            )
+            .unwrap();
+    } else {
+        render_graph.add_node(
+            name(node::DEPTH_TEXTURE),
+            TargetTextureNode::new(
+                target,
+                TextureDescriptor {
+                    size: Extent3d {
+                        depth: 1,
+                        width: 1,
+                        height: 1,
+                    },
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: TextureDimension::D2,
+                    format: TextureFormat::Depth32Float,
+                    usage: TextureUsage::OUTPUT_ATTACHMENT,
+                },
+            ),
+        );
+        render_graph
+            .add_slot_edge(
+                name(node::DEPTH_TEXTURE),
+                TargetTextureNode::OUT_TEXTURE,
+                name(node::RAY_PASS),
+                "depth",
+            )
+            .unwrap();
+    }
+
+    // High dynamic range output of the ray pass
+    for &(texture, format, attachment) in &[
+        (node::HDR_TEXTURE, HDR_FORMAT, "color_attachment"),
+        (node::VELOCITY_TEXTURE, VELOCITY_FORMAT, "velocity_attachment"),
+    ] {
+        render_graph.add_node(name(texture), sampled_target_texture_node(target, format));
+        render_graph
+            .add_slot_edge(
+                name(texture),
+                TargetTextureNode::OUT_TEXTURE,
+                name(node::RAY_PASS),
+                attachment,
+            )
+            .unwrap();
+    }
+
+    if has_history {
+        // Global illumination history, alternating between two textures
+        // so that the previous frame is read while the current one is written
+        render_graph.add_node(
+            name(node::GI_HISTORY_SEQUENCING_NODE),
+            SequencingNode::new(2),
+        );
+        for (i, &history_texture) in [node::GI_HISTORY_TEXTURE, node::ALT_GI_HISTORY_TEXTURE]
+            .iter()
+            .enumerate()
         {
-            // Build render graph
-            let resources = app.resources_mut();
-            let msaa = resources.get::<Msaa>().unwrap();
-            let mut render_graph = resources.get_mut::<RenderGraph>().unwrap();
-            let mut ray_pass_node = PassNode::<&RayPass>::new(PassDescriptor {
-                color_attachments: vec![RenderPassColorAttachmentDescriptor {
+            render_graph.add_node(
+                name(history_texture),
+                sampled_target_texture_node(target, GI_HISTORY_FORMAT),
+            );
+            render_graph
+                .add_slot_edge(
+                    name(history_texture),
+                    TargetTextureNode::OUT_TEXTURE,
+                    name(node::GI_HISTORY_SEQUENCING_NODE),
+                    i,
+                )
+                .unwrap();
+        }
+        render_graph
+            .add_slot_edge(
+                name(node::GI_HISTORY_SEQUENCING_NODE),
+                SequencingNode::OUT_TEXTURE,
+                name(node::RAY_PASS),
+                "history_attachment",
+            )
+            .unwrap();
+        render_graph
+            .add_slot_edge(
+                name(node::GI_HISTORY_SEQUENCING_NODE),
+                SequencingNode::OUT_PREVIOUS_TEXTURE,
+                name(node::RAY_PASS),
+                "GIHistory",
+            )
+            .unwrap();
+    } else {
+        // Written by the ray pass, but never read back
+        render_graph.add_node(
+            name(node::GI_HISTORY_TEXTURE),
+            sampled_target_texture_node(target, GI_HISTORY_FORMAT),
+        );
+        render_graph
+            .add_slot_edge(
+                name(node::GI_HISTORY_TEXTURE),
+                TargetTextureNode::OUT_TEXTURE,
+                name(node::RAY_PASS),
+                "history_attachment",
+            )
+            .unwrap();
+    }
+
+    let tonemapping_input = if has_history {
+        // Temporal anti-aliasing, blending the ray pass output with the previous frames
+        // moved along the motion of the surfaces
+        let mut taa_pass_node = FullscreenPassNode::new(
+            TAA_PIPELINE_HANDLE.typed(),
+            vec![
+                RenderPassColorAttachmentDescriptor {
                     attachment: TextureAttachment::Input("color_attachment".to_string()),
                     resolve_target: None,
                     ops: Operations {
-                        load: LoadOp::Load,
+                        load: LoadOp::Clear(Color::rgba(0.0, 0.0, 0.0, 0.0)),
                         store: true,
                     },
-                }],
-                depth_stencil_attachment: Some(RenderPassDepthStencilAttachmentDescriptor {
-                    attachment: TextureAttachment::Input("depth".to_string()),
-                    depth_ops: Some(Operations {
-                        load: LoadOp::Load,
+                },
+                RenderPassColorAttachmentDescriptor {
+                    attachment: TextureAttachment::Input("history_attachment".to_string()),
+                    resolve_target: None,
+                    ops: Operations {
+                        load: LoadOp::Clear(Color::rgba(0.0, 0.0, 0.0, 0.0)),
                         store: true,
-                    }),
-                    stencil_ops: None,
-                }),
-                sample_count: 1,
-            });
-
-            ray_pass_node.add_camera(base_render_graph::camera::CAMERA_3D);
-            render_graph.add_node(node::RAY_PASS, ray_pass_node);
-            render_graph
-                .add_node_edge(base_render_graph::node::TEXTURE_COPY, node::RAY_PASS)
-                .unwrap();
+                    },
+                },
+            ],
+        );
+        taa_pass_node.add_texture("RayColor", FilterMode::Nearest);
+        taa_pass_node.add_texture("Velocity", FilterMode::Nearest);
+        // The history is sampled between pixels where the surfaces moved
+        taa_pass_node.add_texture("TaaHistory", FilterMode::Linear);
+        render_graph.add_node(name(node::TAA_PASS), taa_pass_node);
+        render_graph
+            .add_node_edge(name(node::RAY_PASS), name(node::TAA_PASS))
+            .unwrap();
+        for &(texture, slot) in &[
+            (node::HDR_TEXTURE, "RayColor"),
+            (node::VELOCITY_TEXTURE, "Velocity"),
+        ] {
             render_graph
-                .add_node_edge(base_render_graph::node::SHARED_BUFFERS, node::RAY_PASS)
+                .add_slot_edge(
+                    name(texture),
+                    TargetTextureNode::OUT_TEXTURE,
+                    name(node::TAA_PASS),
+                    slot,
+                )
                 .unwrap();
+        }
+        render_graph.add_node(
+            name(node::TAA_OUTPUT_TEXTURE),
+            sampled_target_texture_node(target, HDR_FORMAT),
+        );
+        render_graph
+            .add_slot_edge(
+                name(node::TAA_OUTPUT_TEXTURE),
+                TargetTextureNode::OUT_TEXTURE,
+                name(node::TAA_PASS),
+                "color_attachment",
+            )
+            .unwrap();
+        // The history alternates between two textures like the global illumination history
+        render_graph.add_node(
+            name(node::TAA_HISTORY_SEQUENCING_NODE),
+            SequencingNode::new(2),
+        );
+        for (i, &history_texture) in [node::TAA_HISTORY_TEXTURE, node::ALT_TAA_HISTORY_TEXTURE]
+            .iter()
+            .enumerate()
+        {
+            render_graph.add_node(
+                name(history_texture),
+                sampled_target_texture_node(target, HDR_FORMAT),
+            );
             render_graph
-                .add_node_edge(base_render_graph::node::CAMERA_3D, node::RAY_PASS)
+                .add_slot_edge(
+                    name(history_texture),
+                    TargetTextureNode::OUT_TEXTURE,
+                    name(node::TAA_HISTORY_SEQUENCING_NODE),
+                    i,
+                )
                 .unwrap();
+        }
+        render_graph
+            .add_slot_edge(
+                name(node::TAA_HISTORY_SEQUENCING_NODE),
+                SequencingNode::OUT_TEXTURE,
+                name(node::TAA_PASS),
+                "history_attachment",
+            )
+            .unwrap();
+        render_graph
+            .add_slot_edge(
+                name(node::TAA_HISTORY_SEQUENCING_NODE),
+                SequencingNode::OUT_PREVIOUS_TEXTURE,
+                name(node::TAA_PASS),
+                "TaaHistory",
+            )
+            .unwrap();
+        name(node::TAA_OUTPUT_TEXTURE)
+    } else {
+        name(node::HDR_TEXTURE)
+    };
 
-
-            // Alt depth texture
-            render_graph.add_node(
-                node::DEPTH_SEQUENCING_NODE,
-                SequencingNode::new(2),
-            );
+    // Tone mapping into the target
+    let load = match target {
+        // Over the main pass, or the other viewports of the window
+        RayPassTarget::Window(window) if window.is_primary() => LoadOp::Load,
+        RayPassTarget::Viewport { .. } => LoadOp::Load,
+        _ => LoadOp::Clear(Color::rgba(0.0, 0.0, 0.0, 0.0)),
+    };
+    let mut tonemapping_pass_node = FullscreenPassNode::new(
+        TONEMAPPING_PIPELINE_HANDLE.typed(),
+        vec![RenderPassColorAttachmentDescriptor {
+            attachment: TextureAttachment::Input("color_attachment".to_string()),
+            resolve_target: None,
+            ops: Operations { load, store: true },
+        }],
+    );
+    tonemapping_pass_node.add_texture("HdrTexture", FilterMode::Nearest);
+    if let RayPassTarget::Viewport { window, viewport } = target {
+        tonemapping_pass_node.set_viewport(window, viewport);
+    }
+    render_graph.add_node(name(node::TONEMAPPING_PASS), tonemapping_pass_node);
+    render_graph
+        .add_slot_edge(
+            tonemapping_input,
+            TargetTextureNode::OUT_TEXTURE,
+            name(node::TONEMAPPING_PASS),
+            "HdrTexture",
+        )
+        .unwrap();
+    render_graph
+        .add_node_edge(node::TONEMAPPING_NODE, name(node::TONEMAPPING_PASS))
+        .unwrap();
+    render_graph
+        .add_node_edge(name(node::RAY_PASS), name(node::TONEMAPPING_PASS))
+        .unwrap();
+    let (output_node, output_slot) = match target.window() {
+        Some(window) if window.is_primary() => (
+            base_render_graph::node::PRIMARY_SWAP_CHAIN.to_string(),
+            WindowSwapChainNode::OUT_TEXTURE,
+        ),
+        Some(window) => {
+            // Shared by the cameras drawing into the window
+            let swap_chain = format!("{}_{}", node::SWAP_CHAIN, window);
+            if render_graph.get_node_state(swap_chain.clone()).is_err() {
+                render_graph.add_node(swap_chain.clone(), WindowSwapChainNode::new(window));
+            }
+            (swap_chain, WindowSwapChainNode::OUT_TEXTURE)
+        }
+        None => {
             render_graph.add_node(
-                node::ALT_DEPTH_TEXTURE,
-                WindowTextureNode::new(
-                    WindowId::primary(),
+                name(node::OUTPUT_TEXTURE),
+                TargetTextureNode::new(
+                    target,
                     TextureDescriptor {
                         size: Extent3d {
                             depth: 1,
@@ -113,74 +552,62 @@ impl Plugin for OctreeRayTracerPlugin {
                             height: 1,
                         },
                         mip_level_count: 1,
-                        sample_count: msaa.samples,
+                        sample_count: 1,
                         dimension: TextureDimension::D2,
-                        format: TextureFormat::Depth32Float, // PERF: vulkan docs recommend using 24 bit depth for better performance
-                        usage: TextureUsage::OUTPUT_ATTACHMENT,
+                        format: TextureFormat::default(),
+                        usage: TextureUsage::OUTPUT_ATTACHMENT
+                            | TextureUsage::SAMPLED
+                            | TextureUsage::COPY_SRC,
                     },
                 ),
             );
-            render_graph
-                .add_slot_edge(
-                    base_render_graph::node::PRIMARY_SWAP_CHAIN,
-                    WindowSwapChainNode::OUT_TEXTURE,
-                    node::RAY_PASS,
-                    "color_attachment",
-                )
-                .unwrap();
-            render_graph
-                .add_slot_edge(
-                    base_render_graph::node::MAIN_DEPTH_TEXTURE,
-                    WindowTextureNode::OUT_TEXTURE,
-                    node::DEPTH_SEQUENCING_NODE,
-                    0,
-                )
-                .unwrap();
-            render_graph
-                .add_slot_edge(
-                    node::ALT_DEPTH_TEXTURE,
-                    WindowTextureNode::OUT_TEXTURE,
-                    node::DEPTH_SEQUENCING_NODE,
-                    1,
-                )
-                .unwrap();
-            render_graph
-                .add_slot_edge(
-                    node::DEPTH_SEQUENCING_NODE,
-                    SequencingNode::OUT_TEXTURE,
-                    node::RAY_PASS,
-                    "depth",
-                )
-                .unwrap();
+            (name(node::OUTPUT_TEXTURE), TargetTextureNode::OUT_TEXTURE)
+        }
+    };
+    render_graph
+        .add_slot_edge(
+            output_node,
+            output_slot,
+            name(node::TONEMAPPING_PASS),
+            "color_attachment",
+        )
+        .unwrap();
+}
+
+impl Plugin for OctreeRayTracerPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        {
+            // Build render graph
+            let resources = app.resources_mut();
+            let mut render_graph = resources.get_mut::<RenderGraph>().unwrap();
 
             // Octree chunks
             render_graph.add_system_node(node::OCTREE_CHUNK_NODE, ChunkNode::new());
-            render_graph
-                .add_node_edge(node::OCTREE_CHUNK_NODE, node::RAY_PASS)
-                .unwrap();
-
             // Materials
             render_graph.add_system_node(node::MATERIAL_REPO, MaterialNode::new());
-            render_graph
-                .add_node_edge(node::MATERIAL_REPO, node::RAY_PASS)
-                .unwrap();
             // Textures
             render_graph.add_node(node::TEXTURE_REPO, TextureRepoNode::new());
-            render_graph
-                .add_node_edge(node::TEXTURE_REPO, node::RAY_PASS)
-                .unwrap();
-
             // Adding lights
-            render_graph.add_system_node(node::LIGHT_NODE, LightsNode::new(16));
-            render_graph
-                .add_node_edge(node::LIGHT_NODE, node::RAY_PASS)
-                .unwrap();
-
-            // ensure ray pass runs after main pass
-            // So that pixels covered by UI / Mesh rendered objects will not be traced
-            render_graph
-                .add_node_edge(base_render_graph::node::MAIN_PASS, node::RAY_PASS)
-                .unwrap();
+            render_graph.add_system_node(node::LIGHT_NODE, LightsNode::new());
+            // Settings
+            render_graph.add_system_node(node::SETTINGS_NODE, SettingsNode::new());
+            render_graph.add_node(node::TONEMAPPING_NODE, TonemappingNode::new());
+        }
+        let has_primary_window = app
+            .resources()
+            .get::<RenderGraph>()
+            .unwrap()
+            .get_node_state(base_render_graph::node::PRIMARY_SWAP_CHAIN)
+            .is_ok();
+        let screenshots = Screenshots::default();
+        if has_primary_window {
+            add_ray_pass_camera(
+                app.resources(),
+                base_render_graph::camera::CAMERA_3D,
+                RayPassTarget::Window(WindowId::primary()),
+            );
+            let mut render_graph = app.resources().get_mut::<RenderGraph>().unwrap();
+            add_screenshot_nodes(&mut render_graph, &screenshots);
         }
 
         {
@@ -206,6 +633,22 @@ impl Plugin for OctreeRayTracerPlugin {
                 .get_mut::<Assets<Mesh>>()
                 .unwrap()
                 .set_untracked(RAY_PIPELINE_CUBE_HANDLE, mesh);
+
+            let mut quad = Mesh::new(PrimitiveTopology::TriangleStrip);
+            quad.set_indices(Some(Indices::U16(vec![0, 1, 2, 3])));
+            quad.set_attribute(
+                Mesh::ATTRIBUTE_POSITION,
+                vec![
+                    [-1.0, -1.0, 0.0],
+                    [1.0, -1.0, 0.0],
+                    [-1.0, 1.0, 0.0],
+                    [1.0, 1.0, 0.0],
+                ],
+            );
+            app.resources()
+                .get_mut::<Assets<Mesh>>()
+                .unwrap()
+                .set_untracked(FULLSCREEN_QUAD_HANDLE, quad);
         };
         app.add_asset::<Chunk>()
             .add_asset::<MaterialPalette>()
@@ -214,8 +657,21 @@ impl Plugin for OctreeRayTracerPlugin {
             })
             .insert_resource(SunLight {
                 color: Color::rgb_linear(0.8, 0.8, 0.8),
-                direction: Vec3::new(0.5, 0.5, 0.5).normalize(),
-            });
+                direction: Vec3::new(-0.5, -1.0, -0.5).normalize(),
+            })
+            .insert_resource(Sky::default())
+            .insert_resource(RayTracerSettings::default())
+            .insert_resource(Fog::default())
+            .insert_resource(Tonemapping::default())
+            .insert_resource(screenshots)
+            .add_event::<Screenshot>()
+            .add_system(request_screenshots.system())
+            .add_system(save_screenshots.system());
+        // Chunks are only drawn once the texture repo is bound, even when they use no
+        // texture. Keep the repo of apps inserting their own before the plugin.
+        if app.resources().get::<TextureRepo>().is_none() {
+            app.insert_resource(TextureRepo::new(16, 16));
+        }
 
         let resources = app.resources();
         {
@@ -230,20 +686,52 @@ impl Plugin for OctreeRayTracerPlugin {
             PipelineDescriptor {
                 name: Some("octree_raytracing_pipeline".into()),
                 layout: None,
-                color_target_states: vec![ColorTargetState {
-                    format: TextureFormat::default(),
-                    color_blend: BlendState {
-                        src_factor: BlendFactor::SrcAlpha,
-                        dst_factor: BlendFactor::OneMinusSrcAlpha,
-                        operation: BlendOperation::Add,
+                color_target_states: vec![
+                    ColorTargetState {
+                        format: HDR_FORMAT,
+                        color_blend: BlendState {
+                            src_factor: BlendFactor::SrcAlpha,
+                            dst_factor: BlendFactor::OneMinusSrcAlpha,
+                            operation: BlendOperation::Add,
+                        },
+                        // Coverage of the chunks drawn so far, which the tonemapping pass
+                        // divides the colors by. Adding it up would darken overlapping chunks.
+                        alpha_blend: BlendState {
+                            src_factor: BlendFactor::One,
+                            dst_factor: BlendFactor::OneMinusSrcAlpha,
+                            operation: BlendOperation::Add,
+                        },
+                        write_mask: ColorWrite::ALL,
                     },
-                    alpha_blend: BlendState {
-                        src_factor: BlendFactor::One,
-                        dst_factor: BlendFactor::One,
-                        operation: BlendOperation::Add,
+                    ColorTargetState {
+                        format: GI_HISTORY_FORMAT,
+                        color_blend: BlendState {
+                            src_factor: BlendFactor::One,
+                            dst_factor: BlendFactor::Zero,
+                            operation: BlendOperation::Add,
+                        },
+                        alpha_blend: BlendState {
+                            src_factor: BlendFactor::One,
+                            dst_factor: BlendFactor::Zero,
+                            operation: BlendOperation::Add,
+                        },
+                        write_mask: ColorWrite::ALL,
+                    },
+                    ColorTargetState {
+                        format: VELOCITY_FORMAT,
+                        color_blend: BlendState {
+                            src_factor: BlendFactor::One,
+                            dst_factor: BlendFactor::Zero,
+                            operation: BlendOperation::Add,
+                        },
+                        alpha_blend: BlendState {
+                            src_factor: BlendFactor::One,
+                            dst_factor: BlendFactor::Zero,
+                            operation: BlendOperation::Add,
+                        },
+                        write_mask: ColorWrite::ALL,
                     },
-                    write_mask: ColorWrite::ALL,
-                }],
+                ],
                 shader_stages: ShaderStages {
                     vertex: shaders.add(Shader::from_glsl(
                         ShaderStage::Vertex,
@@ -285,5 +773,119 @@ impl Plugin for OctreeRayTracerPlugin {
                 },
             },
         );
+        pipelines.set_untracked(
+            TAA_PIPELINE_HANDLE,
+            PipelineDescriptor {
+                name: Some("taa_pipeline".into()),
+                layout: None,
+                color_target_states: vec![
+                    ColorTargetState {
+                        format: HDR_FORMAT,
+                        color_blend: BlendState {
+                            src_factor: BlendFactor::One,
+                            dst_factor: BlendFactor::Zero,
+                            operation: BlendOperation::Add,
+                        },
+                        alpha_blend: BlendState {
+                            src_factor: BlendFactor::One,
+                            dst_factor: BlendFactor::Zero,
+                            operation: BlendOperation::Add,
+                        },
+                        write_mask: ColorWrite::ALL,
+                    },
+                    ColorTargetState {
+                        format: HDR_FORMAT,
+                        color_blend: BlendState {
+                            src_factor: BlendFactor::One,
+                            dst_factor: BlendFactor::Zero,
+                            operation: BlendOperation::Add,
+                        },
+                        alpha_blend: BlendState {
+                            src_factor: BlendFactor::One,
+                            dst_factor: BlendFactor::Zero,
+                            operation: BlendOperation::Add,
+                        },
+                        write_mask: ColorWrite::ALL,
+                    },
+                ],
+                shader_stages: ShaderStages {
+                    vertex: shaders.add(Shader::from_glsl(
+                        ShaderStage::Vertex,
+                        include_str!("../../assets/shaders/fullscreen.vert"),
+                    )),
+                    fragment: Some(shaders.add(Shader::from_glsl(
+                        ShaderStage::Fragment,
+                        include_str!("../../assets/shaders/taa.frag"),
+                    ))),
+                },
+                primitive: PrimitiveState {
+                    topology: PrimitiveTopology::TriangleStrip,
+                    strip_index_format: Some(IndexFormat::Uint16),
+                    front_face: FrontFace::Ccw,
+                    cull_mode: CullMode::None,
+                    polygon_mode: PolygonMode::Fill,
+                },
+                depth_stencil: None,
+                multisample: MultisampleState {
+                    count: 1,
+                    mask: !0,
+                    alpha_to_coverage_enabled: false,
+                },
+            },
+        );
+        let tonemapping_pipeline = PipelineDescriptor {
+            name: Some("tonemapping_pipeline".into()),
+            layout: None,
+            color_target_states: vec![ColorTargetState {
+                format: TextureFormat::default(),
+                color_blend: BlendState {
+                    src_factor: BlendFactor::SrcAlpha,
+                    dst_factor: BlendFactor::OneMinusSrcAlpha,
+                    operation: BlendOperation::Add,
+                },
+                alpha_blend: BlendState {
+                    src_factor: BlendFactor::One,
+                    dst_factor: BlendFactor::One,
+                    operation: BlendOperation::Add,
+                },
+                write_mask: ColorWrite::ALL,
+            }],
+            shader_stages: ShaderStages {
+                vertex: shaders.add(Shader::from_glsl(
+                    ShaderStage::Vertex,
+                    include_str!("../../assets/shaders/fullscreen.vert"),
+                )),
+                fragment: Some(shaders.add(Shader::from_glsl(
+                    ShaderStage::Fragment,
+                    include_str!("../../assets/shaders/tonemapping.frag"),
+                ))),
+            },
+            primitive: PrimitiveState {
+                topology: PrimitiveTopology::TriangleStrip,
+                strip_index_format: Some(IndexFormat::Uint16),
+                front_face: FrontFace::Ccw,
+                cull_mode: CullMode::None,
+                polygon_mode: PolygonMode::Fill,
+            },
+            depth_stencil: None,
+            multisample: MultisampleState {
+                count: 1,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+        };
+        // Screenshots are tone mapped into a cleared texture. Replacing its texels rather
+        // than blending into them keeps the colors of translucent pixels unpremultiplied.
+        let mut screenshot_pipeline = tonemapping_pipeline.clone();
+        screenshot_pipeline.name = Some("screenshot_pipeline".into());
+        let replace = BlendState {
+            src_factor: BlendFactor::One,
+            dst_factor: BlendFactor::Zero,
+            operation: BlendOperation::Add,
+        };
+        screenshot_pipeline.color_target_states[0].color_blend = replace.clone();
+        screenshot_pipeline.color_target_states[0].alpha_blend = replace;
+        pipelines.set_untracked(TONEMAPPING_PIPELINE_HANDLE, tonemapping_pipeline);
+        pipelines.set_untracked(SCREENSHOT_PIPELINE_HANDLE, screenshot_pipeline);
     }
 }
@@ -0,0 +1,361 @@
+use std::borrow::Cow;
+use crate::raytracer::{RayPass, RAY_PIPELINE_CUBE_HANDLE};
+use bevy::core::AsBytes;
+use bevy::prelude::*;
+use bevy::render::camera::Camera;
+use bevy::render::mesh::{INDEX_BUFFER_ASSET_INDEX, VERTEX_ATTRIBUTE_BUFFER_ID};
+use bevy::render::pass::{
+    PassDescriptor, RenderPassColorAttachmentDescriptor,
+    RenderPassDepthStencilAttachmentDescriptor, TextureAttachment,
+};
+use bevy::render::pipeline::{
+    BindGroupDescriptor, IndexFormat, PipelineCompiler, PipelineDescriptor,
+    PipelineSpecialization, PrimitiveTopology,
+};
+use bevy::render::render_graph::{Node, ResourceSlotInfo, ResourceSlots};
+use bevy::render::renderer::{
+    BindGroup, BindGroupId, BufferId, BufferInfo, BufferUsage, RenderContext,
+    RenderResourceBinding, RenderResourceBindings, RenderResourceType, SamplerId,
+};
+use bevy::render::shader::ShaderSpecialization;
+use bevy::render::texture::{AddressMode, FilterMode, SamplerDescriptor};
+
+/// Draws the proxy cube of every visible [RayPass] chunk seen by a camera.
+///
+/// Like a [FullscreenPassNode](super::fullscreen_pass_node::FullscreenPassNode), and unlike
+/// a `PassNode`, the bind groups are built when the node runs, so the textures of its input
+/// slots are the ones given this very frame. History textures can therefore be read from
+/// the `OUT_PREVIOUS_TEXTURE` of a [SequencingNode](super::sequencing_node::SequencingNode)
+/// while the `OUT_TEXTURE` is written.
+///
+/// Textures are bound by the name of their input slot, with a sampler named
+/// `{name}Sampler`. The `Camera` uniform is the binding of the camera, the `InverseCamera`
+/// uniform holds the inverse of its view projection matrix, and the other
+/// bindings are taken from the `RenderPipelines` of the chunk, then from the
+/// `RenderResourceBindings` resource, by name.
+#[derive(Debug)]
+pub struct RayPassNode {
+    pipeline: Handle<PipelineDescriptor>,
+    camera: String,
+    descriptor: PassDescriptor,
+    inputs: Vec<ResourceSlotInfo>,
+    /// Number of attachment inputs, color attachments first, followed by the sampled textures
+    attachment_count: usize,
+    filters: Vec<FilterMode>,
+    samplers: Vec<(FilterMode, SamplerId)>,
+    shader_defs: Vec<String>,
+    bindings: RenderResourceBindings,
+    /// Inverse of the view projection matrix of the camera, computed once per frame rather
+    /// than by every fragment
+    inverse_camera_buffer: Option<BufferId>,
+    specialized_pipeline: Option<(Handle<PipelineDescriptor>, Vec<BindGroupDescriptor>)>,
+}
+
+impl RayPassNode {
+    /// Attachments read from input slots are given as `TextureAttachment::Input`
+    pub fn new(
+        pipeline: Handle<PipelineDescriptor>,
+        camera: &str,
+        color_attachments: Vec<RenderPassColorAttachmentDescriptor>,
+        depth_stencil_attachment: RenderPassDepthStencilAttachmentDescriptor,
+    ) -> Self {
+        let inputs: Vec<_> = color_attachments
+            .iter()
+            .map(|attachment| &attachment.attachment)
+            .chain(std::iter::once(&depth_stencil_attachment.attachment))
+            .filter_map(|attachment| match attachment {
+                TextureAttachment::Input(name) => Some(ResourceSlotInfo {
+                    name: Cow::Owned(name.clone()),
+                    resource_type: RenderResourceType::Texture,
+                }),
+                _ => None,
+            })
+            .collect();
+        RayPassNode {
+            pipeline,
+            camera: camera.to_string(),
+            descriptor: PassDescriptor {
+                color_attachments,
+                depth_stencil_attachment: Some(depth_stencil_attachment),
+                sample_count: 1,
+            },
+            attachment_count: inputs.len(),
+            inputs,
+            filters: Vec::new(),
+            samplers: Vec::new(),
+            shader_defs: Vec::new(),
+            bindings: Default::default(),
+            inverse_camera_buffer: None,
+            specialized_pipeline: None,
+        }
+    }
+
+    /// Adds an input slot for a texture sampled by the shader under the name `name`
+    pub fn add_texture(&mut self, name: &'static str, filter: FilterMode) {
+        self.inputs.push(ResourceSlotInfo {
+            name: Cow::Borrowed(name),
+            resource_type: RenderResourceType::Texture,
+        });
+        self.filters.push(filter);
+    }
+
+    /// Defines `name` in the shaders of the pipeline
+    pub fn add_shader_def(&mut self, name: &str) {
+        self.shader_defs.push(name.to_string());
+    }
+
+    fn sampler(&mut self, render_context: &mut dyn RenderContext, filter: FilterMode) -> SamplerId {
+        if let Some(&(_, sampler)) = self.samplers.iter().find(|(f, _)| *f == filter) {
+            return sampler;
+        }
+        let sampler = render_context
+            .resources()
+            .create_sampler(&SamplerDescriptor {
+                address_mode_u: AddressMode::ClampToEdge,
+                address_mode_v: AddressMode::ClampToEdge,
+                address_mode_w: AddressMode::ClampToEdge,
+                mag_filter: filter,
+                min_filter: filter,
+                mipmap_filter: FilterMode::Nearest,
+                lod_min_clamp: 0.0,
+                lod_max_clamp: std::f32::MAX,
+                compare_function: None,
+                anisotropy_clamp: None,
+                border_color: None,
+            });
+        self.samplers.push((filter, sampler));
+        sampler
+    }
+
+    fn write_inverse_camera(&mut self, render_context: &mut dyn RenderContext, view_proj: Mat4) {
+        let size = std::mem::size_of::<Mat4>();
+        let buffer = match self.inverse_camera_buffer {
+            Some(buffer) => buffer,
+            None => {
+                let buffer = render_context.resources().create_buffer(BufferInfo {
+                    size,
+                    buffer_usage: BufferUsage::UNIFORM | BufferUsage::COPY_DST,
+                    ..Default::default()
+                });
+                self.bindings.set(
+                    "InverseCamera",
+                    RenderResourceBinding::Buffer {
+                        buffer,
+                        range: 0..size as u64,
+                        dynamic_index: None,
+                    },
+                );
+                self.inverse_camera_buffer = Some(buffer);
+                buffer
+            }
+        };
+        let staging_buffer = render_context.resources().create_buffer(BufferInfo {
+            size,
+            buffer_usage: BufferUsage::MAP_WRITE | BufferUsage::COPY_SRC,
+            mapped_at_creation: true,
+        });
+        render_context.resources().write_mapped_buffer(
+            staging_buffer,
+            0..size as u64,
+            &mut |data: &mut [u8], _renderer| {
+                data.copy_from_slice(view_proj.inverse().to_cols_array().as_bytes());
+            },
+        );
+        render_context.resources().unmap_buffer(staging_buffer);
+        render_context.copy_buffer_to_buffer(staging_buffer, 0, buffer, 0, size as u64);
+        render_context.resources().remove_buffer(staging_buffer);
+    }
+}
+
+/// Builds the bind group of `descriptor` from the first of `bindings` holding each binding.
+/// Returns `None` while some of them are not uploaded yet.
+fn bind_group(
+    render_context: &mut dyn RenderContext,
+    descriptor: &BindGroupDescriptor,
+    bindings: &[&RenderResourceBindings],
+) -> Option<BindGroupId> {
+    let mut bind_group = BindGroup::build();
+    for binding in descriptor.bindings.iter() {
+        let resource = bindings
+            .iter()
+            .find_map(|bindings| bindings.get(&binding.name))?;
+        bind_group = bind_group.add_binding(binding.index, resource.clone());
+    }
+    let bind_group = bind_group.finish();
+    if !render_context
+        .resources()
+        .bind_group_exists(descriptor.id, bind_group.id)
+    {
+        render_context
+            .resources()
+            .create_bind_group(descriptor.id, &bind_group);
+    }
+    Some(bind_group.id)
+}
+
+impl Node for RayPassNode {
+    fn input(&self) -> &[ResourceSlotInfo] {
+        &self.inputs
+    }
+
+    fn update(
+        &mut self,
+        world: &World,
+        resources: &Resources,
+        render_context: &mut dyn RenderContext,
+        input: &ResourceSlots,
+        _output: &mut ResourceSlots,
+    ) {
+        let cube_handle: Handle<Mesh> = RAY_PIPELINE_CUBE_HANDLE.typed();
+        if self.specialized_pipeline.is_none() {
+            let meshes = resources.get::<Assets<Mesh>>().unwrap();
+            let cube = meshes.get(&cube_handle).unwrap();
+            let mut pipeline_compiler = resources.get_mut::<PipelineCompiler>().unwrap();
+            let mut pipelines = resources.get_mut::<Assets<PipelineDescriptor>>().unwrap();
+            let mut shaders = resources.get_mut::<Assets<Shader>>().unwrap();
+            let specialized_pipeline = pipeline_compiler.compile_pipeline(
+                render_context.resources(),
+                &mut pipelines,
+                &mut shaders,
+                &self.pipeline,
+                &PipelineSpecialization {
+                    primitive_topology: PrimitiveTopology::TriangleStrip,
+                    strip_index_format: Some(IndexFormat::Uint16),
+                    vertex_buffer_layout: cube.get_vertex_buffer_layout(),
+                    shader_specialization: ShaderSpecialization {
+                        shader_defs: self.shader_defs.iter().cloned().collect(),
+                    },
+                    ..Default::default()
+                },
+            );
+            let bind_groups = pipelines
+                .get(&specialized_pipeline)
+                .unwrap()
+                .get_layout()
+                .unwrap()
+                .bind_groups
+                .clone();
+            self.specialized_pipeline = Some((specialized_pipeline, bind_groups));
+        }
+
+        for (i, filter) in self.filters.clone().into_iter().enumerate() {
+            let slot = self.attachment_count + i;
+            let name = self.inputs[slot].name.to_string();
+            let sampler = self.sampler(render_context, filter);
+            let texture = input.get(slot).unwrap().get_texture().unwrap();
+            self.bindings.set(&name, RenderResourceBinding::Texture(texture));
+            self.bindings
+                .set(&format!("{}Sampler", name), RenderResourceBinding::Sampler(sampler));
+        }
+
+        let render_resource_bindings = resources.get::<RenderResourceBindings>().unwrap();
+        // Uploaded under the name of the camera by its camera node
+        match render_resource_bindings.get(&self.camera) {
+            Some(camera) => self.bindings.set("Camera", camera.clone()),
+            None => return,
+        }
+        let view_proj = world
+            .query::<(&Camera, &GlobalTransform)>()
+            .find(|(camera, _)| camera.name.as_deref() == Some(self.camera.as_str()))
+            .map(|(camera, global_transform)| {
+                camera.projection_matrix * global_transform.compute_matrix().inverse()
+            });
+        match view_proj {
+            Some(view_proj) => self.write_inverse_camera(render_context, view_proj),
+            None => return,
+        }
+
+        // Bind groups shared by all chunks, and the ones of each chunk
+        let (pipeline, bind_group_descriptors) = self.specialized_pipeline.as_ref().unwrap();
+        let mut shared_bind_groups = Vec::new();
+        let mut chunk_descriptors = Vec::new();
+        for descriptor in bind_group_descriptors.iter() {
+            match bind_group(
+                render_context,
+                descriptor,
+                &[&self.bindings, &render_resource_bindings],
+            ) {
+                Some(bind_group) => {
+                    shared_bind_groups.push((descriptor.index, descriptor.id, bind_group))
+                }
+                None => chunk_descriptors.push(descriptor),
+            }
+        }
+        let mut chunk_bind_groups = Vec::new();
+        for (visible, render_pipelines) in world
+            .query_filtered::<(&Visible, &RenderPipelines), With<RayPass>>()
+        {
+            if !visible.is_visible {
+                continue;
+            }
+            let bind_groups: Option<Vec<_>> = chunk_descriptors
+                .iter()
+                .map(|descriptor| {
+                    bind_group(
+                        render_context,
+                        descriptor,
+                        &[
+                            &render_pipelines.bindings,
+                            &self.bindings,
+                            &render_resource_bindings,
+                        ],
+                    )
+                    .map(|bind_group| (descriptor.index, descriptor.id, bind_group))
+                })
+                .collect();
+            // Chunks not uploaded yet are skipped
+            if let Some(bind_groups) = bind_groups {
+                chunk_bind_groups.push(bind_groups);
+            }
+        }
+
+        let mut descriptor = self.descriptor.clone();
+        for attachment in descriptor
+            .color_attachments
+            .iter_mut()
+            .map(|attachment| &mut attachment.attachment)
+            .chain(
+                descriptor
+                    .depth_stencil_attachment
+                    .iter_mut()
+                    .map(|attachment| &mut attachment.attachment),
+            )
+        {
+            if let TextureAttachment::Input(name) = attachment {
+                let texture = input.get(name.as_str()).unwrap().get_texture().unwrap();
+                *attachment = TextureAttachment::Id(texture);
+            }
+        }
+        let vertex_buffer = render_context
+            .resources()
+            .get_asset_resource(&cube_handle, VERTEX_ATTRIBUTE_BUFFER_ID)
+            .and_then(|resource| resource.get_buffer());
+        let index_buffer = render_context
+            .resources()
+            .get_asset_resource(&cube_handle, INDEX_BUFFER_ASSET_INDEX)
+            .and_then(|resource| resource.get_buffer());
+        let (vertex_buffer, index_buffer) = match (vertex_buffer, index_buffer) {
+            (Some(vertex_buffer), Some(index_buffer)) => (vertex_buffer, index_buffer),
+            _ => return,
+        };
+        // The pass still runs without chunks, clearing the attachments
+        render_context.begin_pass(
+            &descriptor,
+            &render_resource_bindings,
+            &mut |render_pass| {
+                render_pass.set_pipeline(pipeline);
+                render_pass.set_vertex_buffer(0, vertex_buffer, 0);
+                render_pass.set_index_buffer(index_buffer, 0, IndexFormat::Uint16);
+                for &(index, descriptor_id, bind_group_id) in shared_bind_groups.iter() {
+                    render_pass.set_bind_group(index, descriptor_id, bind_group_id, None);
+                }
+                for bind_groups in chunk_bind_groups.iter() {
+                    for &(index, descriptor_id, bind_group_id) in bind_groups.iter() {
+                        render_pass.set_bind_group(index, descriptor_id, bind_group_id, None);
+                    }
+                    render_pass.draw_indexed(0..14, 0, 0..1);
+                }
+            },
+        );
+    }
+}
@@ -0,0 +1,159 @@
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::sync::{Arc, Mutex};
+use crate::raytracer::target::RayPassTarget;
+use bevy::prelude::*;
+use bevy::render::render_graph::{Node, ResourceSlotInfo, ResourceSlots};
+use bevy::render::renderer::{
+    BufferId, BufferInfo, BufferMapMode, BufferUsage, RenderContext, RenderResourceType,
+};
+use bevy::render::texture::{Extent3d, TextureFormat};
+use image::RgbaImage;
+
+/// Rows of a texture copied into a buffer are aligned to this many bytes
+const COPY_BYTES_PER_ROW_ALIGNMENT: u32 = 256;
+
+/// Requests images from a [TextureReadbackNode], and receives them.
+/// Clones share the same node.
+#[derive(Debug, Clone, Default)]
+pub struct TextureReadback(Arc<Mutex<ReadbackState>>);
+
+#[derive(Debug)]
+enum ReadbackState {
+    Idle,
+    Requested,
+    Done(RgbaImage),
+}
+
+impl Default for ReadbackState {
+    fn default() -> Self {
+        ReadbackState::Idle
+    }
+}
+
+impl TextureReadback {
+    /// Reads back the texture of the next frame rendered
+    pub fn request(&self) {
+        *self.0.lock().unwrap() = ReadbackState::Requested;
+    }
+
+    /// The image read back since the last request, available the frame after it was rendered
+    pub fn take(&self) -> Option<RgbaImage> {
+        let mut state = self.0.lock().unwrap();
+        match std::mem::take(&mut *state) {
+            ReadbackState::Done(image) => Some(image),
+            other => {
+                *state = other;
+                None
+            }
+        }
+    }
+
+    pub(crate) fn is_requested(&self) -> bool {
+        matches!(*self.0.lock().unwrap(), ReadbackState::Requested)
+    }
+}
+
+/// Copies its input texture, of the size of a [RayPassTarget] and the default texture format,
+/// into a buffer when a [TextureReadback] requests it.
+///
+/// The copy only happens once the commands of the frame are submitted, so the buffer is
+/// mapped and read into an image on the next frame.
+#[derive(Debug)]
+pub struct TextureReadbackNode {
+    target: RayPassTarget,
+    readback: TextureReadback,
+    /// Buffer the texture was copied into last frame, with the size of the texture
+    copy: Option<(BufferId, Extent3d)>,
+}
+
+impl TextureReadbackNode {
+    pub const IN_TEXTURE: &'static str = "texture";
+
+    pub fn new(target: RayPassTarget, readback: TextureReadback) -> Self {
+        TextureReadbackNode {
+            target,
+            readback,
+            copy: None,
+        }
+    }
+}
+
+/// Bytes of each row of a texture of the default format `width` texels wide, once copied
+/// into a buffer: rows are padded to a multiple of 256 bytes.
+pub fn padded_bytes_per_row(width: u32) -> u32 {
+    let unpadded = width * std::mem::size_of::<u32>() as u32;
+    (unpadded + COPY_BYTES_PER_ROW_ALIGNMENT - 1) / COPY_BYTES_PER_ROW_ALIGNMENT
+        * COPY_BYTES_PER_ROW_ALIGNMENT
+}
+
+impl Node for TextureReadbackNode {
+    fn input(&self) -> &[ResourceSlotInfo] {
+        static INPUT: &[ResourceSlotInfo] = &[ResourceSlotInfo {
+            name: Cow::Borrowed(TextureReadbackNode::IN_TEXTURE),
+            resource_type: RenderResourceType::Texture,
+        }];
+        INPUT
+    }
+
+    fn update(
+        &mut self,
+        _world: &World,
+        resources: &Resources,
+        render_context: &mut dyn RenderContext,
+        input: &ResourceSlots,
+        _output: &mut ResourceSlots,
+    ) {
+        if let Some((buffer, size)) = self.copy.take() {
+            let bytes_per_row = padded_bytes_per_row(size.width) as usize;
+            let row_size = size.width as usize * std::mem::size_of::<u32>();
+            let image = RefCell::new(None);
+            render_context
+                .resources()
+                .map_buffer(buffer, BufferMapMode::Read);
+            render_context.resources().read_mapped_buffer(
+                buffer,
+                0..(bytes_per_row * size.height as usize) as u64,
+                &|data: &[u8], _renderer| {
+                    let mut pixels = Vec::with_capacity(row_size * size.height as usize);
+                    for row in data.chunks_exact(bytes_per_row) {
+                        pixels.extend_from_slice(&row[..row_size]);
+                    }
+                    if TextureFormat::default() == TextureFormat::Bgra8UnormSrgb {
+                        for pixel in pixels.chunks_exact_mut(4) {
+                            pixel.swap(0, 2);
+                        }
+                    }
+                    *image.borrow_mut() = RgbaImage::from_raw(size.width, size.height, pixels);
+                },
+            );
+            render_context.resources().unmap_buffer(buffer);
+            render_context.resources().remove_buffer(buffer);
+            if let Some(image) = image.into_inner() {
+                *self.readback.0.lock().unwrap() = ReadbackState::Done(image);
+            }
+            return;
+        }
+        if !self.readback.is_requested() {
+            return;
+        }
+        let size = self.target.size(&resources.get::<Windows>().unwrap());
+        let bytes_per_row = padded_bytes_per_row(size.width);
+        let buffer = render_context.resources().create_buffer(BufferInfo {
+            size: (bytes_per_row * size.height) as usize,
+            buffer_usage: BufferUsage::MAP_READ | BufferUsage::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let texture = input.get(0).unwrap().get_texture().unwrap();
+        render_context.copy_texture_to_buffer(
+            texture,
+            [0, 0, 0],
+            0,
+            buffer,
+            0,
+            bytes_per_row,
+            size,
+        );
+        self.copy = Some((buffer, size));
+    }
+}
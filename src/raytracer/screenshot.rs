@@ -0,0 +1,179 @@
+use std::path::PathBuf;
+use crate::raytracer::fullscreen_pass_node::FullscreenPassNode;
+use crate::raytracer::readback_node::{TextureReadback, TextureReadbackNode};
+use crate::raytracer::target::{RayPassTarget, TargetTextureNode};
+use crate::raytracer::{camera_node_name, node};
+use bevy::log::error;
+use bevy::prelude::*;
+use bevy::reflect::TypeUuid;
+use bevy::render::pass::{
+    LoadOp, Operations, RenderPassColorAttachmentDescriptor, TextureAttachment,
+};
+use bevy::render::pipeline::PipelineDescriptor;
+use bevy::render::render_graph::base::camera::CAMERA_3D;
+use bevy::render::render_graph::{Node, RenderGraph, ResourceSlotInfo, ResourceSlots};
+use bevy::render::renderer::RenderContext;
+use bevy::render::texture::{
+    Extent3d, FilterMode, TextureDescriptor, TextureDimension, TextureFormat, TextureUsage,
+};
+use bevy::window::WindowId;
+use image::ImageFormat;
+
+pub const SCREENSHOT_TEXTURE: &str = "screenshot_texture";
+pub const SCREENSHOT_PASS: &str = "screenshot_pass";
+pub const SCREENSHOT_READBACK: &str = "screenshot_readback";
+
+/// The tone mapping pipeline, replacing the texels of the screenshot texture instead of
+/// blending into them, so that the image isn't premultiplied by its alpha
+pub const SCREENSHOT_PIPELINE_HANDLE: HandleUntyped =
+    HandleUntyped::weak_from_u64(PipelineDescriptor::TYPE_UUID, 0x786f7ab62875ebc1);
+
+/// Sent to save the next frame of the 3d camera of the primary window as a PNG image.
+///
+/// Only the tone mapped output of the ray pass is captured: pixels not covered by any chunk
+/// are transparent, and whatever the main pass draws is left out.
+#[derive(Debug, Clone)]
+pub struct Screenshot {
+    pub path: PathBuf,
+}
+
+/// The screenshots waiting for the image of a frame
+#[derive(Debug, Default)]
+pub struct Screenshots {
+    readback: TextureReadback,
+    paths: Vec<PathBuf>,
+}
+
+/// Runs its pass only on the frames read back for screenshots
+#[derive(Debug)]
+struct ScreenshotPassNode {
+    pass: FullscreenPassNode,
+    readback: TextureReadback,
+}
+
+impl Node for ScreenshotPassNode {
+    fn input(&self) -> &[ResourceSlotInfo] {
+        self.pass.input()
+    }
+
+    fn update(
+        &mut self,
+        world: &World,
+        resources: &Resources,
+        render_context: &mut dyn RenderContext,
+        input: &ResourceSlots,
+        output: &mut ResourceSlots,
+    ) {
+        if self.readback.is_requested() {
+            self.pass.update(world, resources, render_context, input, output);
+        }
+    }
+}
+
+/// Tone maps the anti-aliased output of the 3d camera of the primary window once more,
+/// into a texture that can be copied, unlike the swap chain, on the frames screenshots are
+/// taken.
+///
+/// The anti-aliased output is a single texture, so the textures alternated by the
+/// [SequencingNode](super::sequencing_node::SequencingNode) don't matter here.
+pub(crate) fn add_screenshot_nodes(render_graph: &mut RenderGraph, screenshots: &Screenshots) {
+    let target = RayPassTarget::Window(WindowId::primary());
+    render_graph.add_node(
+        SCREENSHOT_TEXTURE,
+        TargetTextureNode::new(
+            target,
+            TextureDescriptor {
+                size: Extent3d {
+                    depth: 1,
+                    width: 1,
+                    height: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: TextureDimension::D2,
+                format: TextureFormat::default(),
+                usage: TextureUsage::OUTPUT_ATTACHMENT | TextureUsage::COPY_SRC,
+            },
+        ),
+    );
+    let mut screenshot_pass_node = FullscreenPassNode::new(
+        SCREENSHOT_PIPELINE_HANDLE.typed(),
+        vec![RenderPassColorAttachmentDescriptor {
+            attachment: TextureAttachment::Input("color_attachment".to_string()),
+            resolve_target: None,
+            ops: Operations {
+                load: LoadOp::Clear(Color::rgba(0.0, 0.0, 0.0, 0.0)),
+                store: true,
+            },
+        }],
+    );
+    screenshot_pass_node.add_texture("HdrTexture", FilterMode::Nearest);
+    render_graph.add_node(
+        SCREENSHOT_PASS,
+        ScreenshotPassNode {
+            pass: screenshot_pass_node,
+            readback: screenshots.readback.clone(),
+        },
+    );
+    render_graph
+        .add_slot_edge(
+            SCREENSHOT_TEXTURE,
+            TargetTextureNode::OUT_TEXTURE,
+            SCREENSHOT_PASS,
+            "color_attachment",
+        )
+        .unwrap();
+    render_graph
+        .add_slot_edge(
+            camera_node_name(CAMERA_3D, node::TAA_OUTPUT_TEXTURE),
+            TargetTextureNode::OUT_TEXTURE,
+            SCREENSHOT_PASS,
+            "HdrTexture",
+        )
+        .unwrap();
+    render_graph
+        .add_node_edge(node::TONEMAPPING_NODE, SCREENSHOT_PASS)
+        .unwrap();
+    render_graph
+        .add_node_edge(camera_node_name(CAMERA_3D, node::TAA_PASS), SCREENSHOT_PASS)
+        .unwrap();
+
+    render_graph.add_node(
+        SCREENSHOT_READBACK,
+        TextureReadbackNode::new(target, screenshots.readback.clone()),
+    );
+    render_graph
+        .add_slot_edge(
+            SCREENSHOT_TEXTURE,
+            TargetTextureNode::OUT_TEXTURE,
+            SCREENSHOT_READBACK,
+            TextureReadbackNode::IN_TEXTURE,
+        )
+        .unwrap();
+    render_graph
+        .add_node_edge(SCREENSHOT_PASS, SCREENSHOT_READBACK)
+        .unwrap();
+}
+
+pub fn request_screenshots(
+    mut events: EventReader<Screenshot>,
+    mut screenshots: ResMut<Screenshots>,
+) {
+    for screenshot in events.iter() {
+        // Screenshots requested while a frame is read back are saved from it
+        if screenshots.paths.is_empty() {
+            screenshots.readback.request();
+        }
+        screenshots.paths.push(screenshot.path.clone());
+    }
+}
+
+pub fn save_screenshots(mut screenshots: ResMut<Screenshots>) {
+    if let Some(image) = screenshots.readback.take() {
+        for path in screenshots.paths.drain(..) {
+            if let Err(err) = image.save_with_format(&path, ImageFormat::Png) {
+                error!("Failed to save screenshot {}: {}", path.display(), err);
+            }
+        }
+    }
+}
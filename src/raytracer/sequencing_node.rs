@@ -4,7 +4,8 @@ use bevy::render::renderer::{RenderResourceId, RenderResourceType, RenderContext
 use bevy::render::render_graph::{Node, ResourceSlotInfo, ResourceSlots};
 use bevy::prelude::*;
 
-/// For each frame, the sequencing node sends one of the input textures as the output
+/// For each frame, the sequencing node sends one of the input textures as the output,
+/// and the one it sent the previous frame as the previous output
 pub struct SequencingNode {
     state: u32,
     inputs: Vec<ResourceSlotInfo>,
@@ -12,6 +13,7 @@ pub struct SequencingNode {
 
 impl SequencingNode {
     pub const OUT_TEXTURE: &'static str = "texture";
+    pub const OUT_PREVIOUS_TEXTURE: &'static str = "previous_texture";
 
     pub fn new(size: u32) -> Self {
         let mut inputs: Vec<ResourceSlotInfo> = Vec::with_capacity(size as usize);
@@ -30,10 +32,16 @@ impl SequencingNode {
 
 impl Node for SequencingNode {
     fn output(&self) -> &[ResourceSlotInfo] {
-        static OUTPUT: &[ResourceSlotInfo] = &[ResourceSlotInfo {
-            name: Cow::Borrowed(SequencingNode::OUT_TEXTURE),
-            resource_type: RenderResourceType::Texture,
-        }];
+        static OUTPUT: &[ResourceSlotInfo] = &[
+            ResourceSlotInfo {
+                name: Cow::Borrowed(SequencingNode::OUT_TEXTURE),
+                resource_type: RenderResourceType::Texture,
+            },
+            ResourceSlotInfo {
+                name: Cow::Borrowed(SequencingNode::OUT_PREVIOUS_TEXTURE),
+                resource_type: RenderResourceType::Texture,
+            },
+        ];
         OUTPUT
     }
 
@@ -51,6 +59,8 @@ impl Node for SequencingNode {
         self.state = self.state % input.len() as u32;
         let resource = input.get(self.state as usize).unwrap();
         output.set(0, resource);
+        let previous = (self.state + input.len() as u32 - 1) % input.len() as u32;
+        output.set(1, input.get(previous as usize).unwrap());
 
         self.state += 1;
     }
@@ -0,0 +1,241 @@
+use bevy::core::Bytes;
+
+/// Quality settings of the ray tracer, uploaded to the `RayTracerSettings` uniform.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RayTracerSettings {
+    pub sun_shadows: ShadowSettings,
+    /// Shadows of point, spot and area lights. Only lights with `shadows` set cast them.
+    pub light_shadows: ShadowSettings,
+    /// Number of points sampled on each area light per pixel and per frame.
+    /// More samples give smoother soft shadows, each one costing a shadow ray.
+    pub area_light_samples: u32,
+    pub ambient_occlusion: AmbientOcclusionSettings,
+    pub global_illumination: GlobalIlluminationSettings,
+    pub reflections: ReflectionSettings,
+    pub temporal_anti_aliasing: TemporalAntiAliasingSettings,
+    /// Shows the number of octree node fetches of the primary rays as a heatmap, from blue
+    /// for rays stopping right away to red for rays running out of steps, instead of the
+    /// shaded voxels. For comparing the cost of traversing different scenes.
+    pub iteration_heatmap: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShadowSettings {
+    pub enabled: bool,
+    /// Maximum number of octree node fetches spent on a single shadow ray.
+    /// Rays running out of steps are considered unoccluded.
+    pub max_iterations: u32,
+    /// Angle between the light and the surface, in radians, below which the surface is
+    /// considered unlit. Rays at grazing angles are the most expensive ones to trace,
+    /// while their contribution is close to zero.
+    pub min_grazing_angle: f32,
+}
+
+/// Ambient occlusion darkens the ambient light in corners and caves by tracing short rays
+/// around each surface and counting the ones hitting a voxel.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AmbientOcclusionSettings {
+    pub enabled: bool,
+    /// Number of rays traced per pixel and per frame.
+    /// More rays give less noise, each one costing an occlusion ray.
+    pub samples: u32,
+    /// Maximum number of octree node fetches spent on a single occlusion ray.
+    pub max_iterations: u32,
+    /// Distance within which a voxel occludes the surface
+    pub distance: f32,
+}
+
+impl Default for AmbientOcclusionSettings {
+    fn default() -> Self {
+        AmbientOcclusionSettings {
+            enabled: true,
+            samples: 4,
+            max_iterations: 32,
+            distance: 2.0,
+        }
+    }
+}
+
+/// One bounce of indirect light, traced with one ray per pixel and per frame.
+/// The noisy results are averaged over frames while the camera and the chunks stay still.
+/// When enabled, it replaces ambient occlusion.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GlobalIlluminationSettings {
+    pub enabled: bool,
+    /// Maximum number of octree node fetches spent on a single bounce ray.
+    pub max_iterations: u32,
+    /// Number of frames after which older frames start fading out of the average.
+    pub max_accumulated_frames: u32,
+}
+
+impl Default for GlobalIlluminationSettings {
+    fn default() -> Self {
+        GlobalIlluminationSettings {
+            enabled: false,
+            max_iterations: 128,
+            max_accumulated_frames: 256,
+        }
+    }
+}
+
+/// Secondary rays for reflective and transparent materials
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReflectionSettings {
+    /// Maximum number of reflections and refractions followed from the first surface hit.
+    /// 0 disables reflections and refractions.
+    pub max_bounces: u32,
+    /// Maximum number of octree node fetches spent on each reflected or refracted ray.
+    pub max_iterations: u32,
+}
+
+impl Default for ReflectionSettings {
+    fn default() -> Self {
+        ReflectionSettings {
+            max_bounces: 2,
+            max_iterations: 256,
+        }
+    }
+}
+
+/// Temporal anti-aliasing smooths voxel edges by shifting the rays of each frame by a
+/// different sub-pixel offset, and blending each frame with the previous ones, reprojected
+/// to follow the camera.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TemporalAntiAliasingSettings {
+    pub enabled: bool,
+    /// Weight of the previous frames in the blend, from 0 to 1.
+    /// Higher weights smooth edges more, but ghost more behind moving objects.
+    pub history_weight: f32,
+}
+
+impl Default for TemporalAntiAliasingSettings {
+    fn default() -> Self {
+        TemporalAntiAliasingSettings {
+            enabled: true,
+            history_weight: 0.9,
+        }
+    }
+}
+
+const TEMPORAL_ANTI_ALIASING_SETTINGS_SIZE: usize = 8;
+
+impl Bytes for TemporalAntiAliasingSettings {
+    fn write_bytes(&self, buffer: &mut [u8]) {
+        (self.enabled as u32).write_bytes(&mut buffer[0..4]);
+        self.history_weight.write_bytes(&mut buffer[4..8]);
+    }
+    fn byte_len(&self) -> usize {
+        TEMPORAL_ANTI_ALIASING_SETTINGS_SIZE
+    }
+}
+
+impl Default for RayTracerSettings {
+    fn default() -> Self {
+        RayTracerSettings {
+            sun_shadows: ShadowSettings::default(),
+            light_shadows: ShadowSettings {
+                max_iterations: 64,
+                ..Default::default()
+            },
+            area_light_samples: 4,
+            ambient_occlusion: AmbientOcclusionSettings::default(),
+            global_illumination: GlobalIlluminationSettings::default(),
+            reflections: ReflectionSettings::default(),
+            temporal_anti_aliasing: TemporalAntiAliasingSettings::default(),
+            iteration_heatmap: false,
+        }
+    }
+}
+
+impl Default for ShadowSettings {
+    fn default() -> Self {
+        ShadowSettings {
+            enabled: true,
+            max_iterations: 256,
+            min_grazing_angle: 0.05,
+        }
+    }
+}
+
+const SHADOW_SETTINGS_SIZE: usize = 12;
+const AMBIENT_OCCLUSION_SETTINGS_SIZE: usize = 16;
+const GLOBAL_ILLUMINATION_SETTINGS_SIZE: usize = 12;
+const REFLECTION_SETTINGS_SIZE: usize = 8;
+/// Size of the `RayTracerSettings` uniform, padded to 16 bytes like std140 blocks
+pub const RAY_TRACER_SETTINGS_SIZE: usize = 192;
+// Offsets of the fields of the uniform, matching the block in ray.frag
+pub const FRAME_INDEX_OFFSET: usize = 28;
+pub const ACCUMULATED_FRAMES_OFFSET: usize = 60;
+pub const TIME_OFFSET: usize = 72;
+pub const FOG_OFFSET: usize = 80;
+pub const PREVIOUS_VIEW_PROJ_OFFSET: usize = 112;
+/// Also the offset of the block in taa.frag, which only reads these settings
+pub const TAA_SETTINGS_OFFSET: usize = 176;
+pub const ITERATION_HEATMAP_OFFSET: usize = 184;
+
+impl Bytes for ShadowSettings {
+    fn write_bytes(&self, buffer: &mut [u8]) {
+        (self.enabled as u32).write_bytes(&mut buffer[0..4]);
+        self.max_iterations.write_bytes(&mut buffer[4..8]);
+        self.min_grazing_angle.sin().write_bytes(&mut buffer[8..12]);
+    }
+    fn byte_len(&self) -> usize {
+        SHADOW_SETTINGS_SIZE
+    }
+}
+
+impl Bytes for AmbientOcclusionSettings {
+    fn write_bytes(&self, buffer: &mut [u8]) {
+        (self.enabled as u32).write_bytes(&mut buffer[0..4]);
+        self.samples.write_bytes(&mut buffer[4..8]);
+        self.max_iterations.write_bytes(&mut buffer[8..12]);
+        self.distance.write_bytes(&mut buffer[12..16]);
+    }
+    fn byte_len(&self) -> usize {
+        AMBIENT_OCCLUSION_SETTINGS_SIZE
+    }
+}
+
+impl Bytes for GlobalIlluminationSettings {
+    fn write_bytes(&self, buffer: &mut [u8]) {
+        (self.enabled as u32).write_bytes(&mut buffer[0..4]);
+        self.max_iterations.write_bytes(&mut buffer[4..8]);
+        self.max_accumulated_frames.write_bytes(&mut buffer[8..12]);
+    }
+    fn byte_len(&self) -> usize {
+        GLOBAL_ILLUMINATION_SETTINGS_SIZE
+    }
+}
+
+impl Bytes for ReflectionSettings {
+    fn write_bytes(&self, buffer: &mut [u8]) {
+        self.max_bounces.write_bytes(&mut buffer[0..4]);
+        self.max_iterations.write_bytes(&mut buffer[4..8]);
+    }
+    fn byte_len(&self) -> usize {
+        REFLECTION_SETTINGS_SIZE
+    }
+}
+
+impl Bytes for RayTracerSettings {
+    /// Writes everything but the frame index, the number of accumulated frames, the time,
+    /// the [Fog](super::fog::Fog) and the previous view projection matrix,
+    /// which are written by the settings node
+    fn write_bytes(&self, buffer: &mut [u8]) {
+        self.sun_shadows.write_bytes(&mut buffer[0..12]);
+        self.light_shadows.write_bytes(&mut buffer[12..24]);
+        self.area_light_samples.write_bytes(&mut buffer[24..28]);
+        self.ambient_occlusion.write_bytes(&mut buffer[32..48]);
+        self.global_illumination.write_bytes(&mut buffer[48..60]);
+        self.reflections.write_bytes(&mut buffer[64..72]);
+        self.temporal_anti_aliasing.write_bytes(
+            &mut buffer
+                [TAA_SETTINGS_OFFSET..TAA_SETTINGS_OFFSET + TEMPORAL_ANTI_ALIASING_SETTINGS_SIZE],
+        );
+        (self.iteration_heatmap as u32)
+            .write_bytes(&mut buffer[ITERATION_HEATMAP_OFFSET..ITERATION_HEATMAP_OFFSET + 4]);
+    }
+    fn byte_len(&self) -> usize {
+        RAY_TRACER_SETTINGS_SIZE
+    }
+}
@@ -0,0 +1,236 @@
+use crate::lights::{AmbientLight, AreaLight, PointLight, Sky, SpotLight, SunLight};
+use crate::material::MaterialPalette;
+use crate::raytracer::chunk::Chunk;
+use crate::raytracer::fog::{Fog, FOG_SIZE};
+use crate::raytracer::settings::{
+    RayTracerSettings, ACCUMULATED_FRAMES_OFFSET, FOG_OFFSET, FRAME_INDEX_OFFSET,
+    PREVIOUS_VIEW_PROJ_OFFSET, RAY_TRACER_SETTINGS_SIZE, TIME_OFFSET,
+};
+use bevy::core::AsBytes;
+use bevy::app::ManualEventReader;
+use bevy::core::Bytes;
+use bevy::prelude::*;
+use bevy::render::camera::Camera;
+use bevy::render::render_graph::base::camera::CAMERA_3D;
+use bevy::render::render_graph::{CommandQueue, Node, ResourceSlots, SystemNode};
+use bevy::render::renderer::{
+    BufferId, BufferInfo, BufferMapMode, BufferUsage, RenderContext, RenderResourceBinding,
+    RenderResourceBindings, RenderResourceContext,
+};
+use bevy::window::WindowResized;
+
+const RAY_TRACER_SETTINGS: &str = "RayTracerSettings";
+/// A Render Graph [Node] that writes the [RayTracerSettings] resource to a GPU buffer
+#[derive(Debug, Default)]
+pub struct SettingsNode {
+    command_queue: CommandQueue,
+}
+
+impl SettingsNode {
+    pub fn new() -> Self {
+        SettingsNode {
+            command_queue: CommandQueue::default(),
+        }
+    }
+}
+
+impl Node for SettingsNode {
+    fn update(
+        &mut self,
+        _world: &World,
+        _resources: &Resources,
+        render_context: &mut dyn RenderContext,
+        _input: &ResourceSlots,
+        _output: &mut ResourceSlots,
+    ) {
+        self.command_queue.execute(render_context);
+    }
+}
+
+impl SystemNode for SettingsNode {
+    fn get_system(&self, commands: &mut Commands) -> Box<dyn System<In = (), Out = ()>> {
+        let system = settings_node_system.system();
+        commands.insert_local_resource(
+            system.id(),
+            SettingsNodeSystemState {
+                command_queue: self.command_queue.clone(),
+                settings_buffer: None,
+                staging_buffer: None,
+                frame_index: 0,
+                accumulated_frames: 0,
+                chunk_event_reader: Default::default(),
+                material_palette_event_reader: Default::default(),
+                window_resized_event_reader: Default::default(),
+                view_proj: None,
+                lighting: None,
+            },
+        );
+        Box::new(system)
+    }
+}
+
+/// Local "settings node system" state
+#[derive(Default)]
+pub struct SettingsNodeSystemState {
+    settings_buffer: Option<BufferId>,
+    staging_buffer: Option<BufferId>,
+    command_queue: CommandQueue,
+    /// Seeds the random numbers of the shader differently every frame
+    frame_index: u32,
+    /// Number of frames the global illumination history has been accumulating for
+    accumulated_frames: u32,
+    chunk_event_reader: ManualEventReader<AssetEvent<Chunk>>,
+    material_palette_event_reader: ManualEventReader<AssetEvent<MaterialPalette>>,
+    window_resized_event_reader: ManualEventReader<WindowResized>,
+    /// View projection matrix of the camera in the previous frame,
+    /// reprojecting the temporal anti-aliasing history
+    view_proj: Option<Mat4>,
+    /// Resources the accumulated light was computed with
+    lighting: Option<Lighting>,
+}
+
+/// The resources affecting the light accumulated by global illumination
+#[derive(Debug, Clone, PartialEq)]
+struct Lighting {
+    sun_light: SunLight,
+    ambient_light: AmbientLight,
+    sky: Sky,
+    fog: Fog,
+    settings: RayTracerSettings,
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn settings_node_system(
+    mut state: Local<SettingsNodeSystemState>,
+    render_resource_context: Res<Box<dyn RenderResourceContext>>,
+    settings: Res<RayTracerSettings>,
+    fog: Res<Fog>,
+    sun_light: Res<SunLight>,
+    ambient_light: Res<AmbientLight>,
+    sky: Res<Sky>,
+    time: Res<Time>,
+    mut render_resource_bindings: ResMut<RenderResourceBindings>,
+    chunk_events: Res<Events<AssetEvent<Chunk>>>,
+    material_palette_events: Res<Events<AssetEvent<MaterialPalette>>>,
+    window_resized_events: Res<Events<WindowResized>>,
+    cameras: Query<(&Camera, &GlobalTransform)>,
+    changed_chunks: Query<&Handle<Chunk>, Changed<Handle<Chunk>>>,
+    changed_lights: Query<
+        Entity,
+        Or<(Changed<PointLight>, Changed<SpotLight>, Changed<AreaLight>)>,
+    >,
+    moved_lights: Query<
+        Entity,
+        (
+            Changed<GlobalTransform>,
+            Or<(With<PointLight>, With<SpotLight>, With<AreaLight>)>,
+        ),
+    >,
+) {
+    let state = &mut *state;
+
+    let view_proj = cameras
+        .iter()
+        .find(|(camera, _)| camera.name.as_deref() == Some(CAMERA_3D))
+        .map(|(camera, global_transform)| {
+            camera.projection_matrix * global_transform.compute_matrix().inverse()
+        })
+        .unwrap_or_else(Mat4::identity);
+    let previous_view_proj = state.view_proj.replace(view_proj).unwrap_or(view_proj);
+
+    // The accumulated light is only valid while the camera, the chunks and everything
+    // lighting them stay still
+    let chunks_changed = state.chunk_event_reader.iter(&chunk_events).count() > 0
+        || changed_chunks.iter().next().is_some();
+    let materials_changed = state
+        .material_palette_event_reader
+        .iter(&material_palette_events)
+        .count()
+        > 0;
+    let window_resized = state
+        .window_resized_event_reader
+        .iter(&window_resized_events)
+        .count()
+        > 0;
+    let camera_moved = view_proj != previous_view_proj;
+    let lights_changed =
+        changed_lights.iter().next().is_some() || moved_lights.iter().next().is_some();
+    let lighting = Lighting {
+        sun_light: sun_light.clone(),
+        ambient_light: ambient_light.clone(),
+        sky: sky.clone(),
+        fog: fog.clone(),
+        settings: settings.clone(),
+    };
+    let lighting_changed = state.lighting.as_ref() != Some(&lighting);
+    if lighting_changed {
+        state.lighting = Some(lighting);
+    }
+    if !settings.global_illumination.enabled
+        || chunks_changed
+        || materials_changed
+        || window_resized
+        || camera_moved
+        || lights_changed
+        || lighting_changed
+    {
+        state.accumulated_frames = 0;
+    }
+
+    let render_resource_context = &**render_resource_context;
+    let size = RAY_TRACER_SETTINGS_SIZE;
+
+    if let Some(staging_buffer) = state.staging_buffer {
+        render_resource_context.map_buffer(staging_buffer, BufferMapMode::Write);
+    } else {
+        let buffer = render_resource_context.create_buffer(BufferInfo {
+            size,
+            buffer_usage: BufferUsage::UNIFORM | BufferUsage::COPY_DST,
+            ..Default::default()
+        });
+        render_resource_bindings.set(
+            RAY_TRACER_SETTINGS,
+            RenderResourceBinding::Buffer {
+                buffer,
+                range: 0..size as u64,
+                dynamic_index: None,
+            },
+        );
+        state.settings_buffer = Some(buffer);
+
+        let staging_buffer = render_resource_context.create_buffer(BufferInfo {
+            size,
+            buffer_usage: BufferUsage::COPY_SRC | BufferUsage::MAP_WRITE,
+            mapped_at_creation: true,
+        });
+        state.staging_buffer = Some(staging_buffer);
+    }
+
+    let staging_buffer = state.staging_buffer.unwrap();
+    render_resource_context.write_mapped_buffer(
+        staging_buffer,
+        0..size as u64,
+        &mut |data, _renderer| {
+            settings.write_bytes(data);
+            state
+                .frame_index
+                .write_bytes(&mut data[FRAME_INDEX_OFFSET..FRAME_INDEX_OFFSET + 4]);
+            state.accumulated_frames.write_bytes(
+                &mut data[ACCUMULATED_FRAMES_OFFSET..ACCUMULATED_FRAMES_OFFSET + 4],
+            );
+            // Drives animated textures
+            (time.seconds_since_startup() as f32)
+                .write_bytes(&mut data[TIME_OFFSET..TIME_OFFSET + 4]);
+            fog.write_bytes(&mut data[FOG_OFFSET..FOG_OFFSET + FOG_SIZE]);
+            data[PREVIOUS_VIEW_PROJ_OFFSET..PREVIOUS_VIEW_PROJ_OFFSET + 64]
+                .copy_from_slice(previous_view_proj.to_cols_array().as_bytes());
+        },
+    );
+    render_resource_context.unmap_buffer(staging_buffer);
+    let settings_buffer = state.settings_buffer.unwrap();
+    state
+        .command_queue
+        .copy_buffer_to_buffer(staging_buffer, 0, settings_buffer, 0, size as u64);
+    state.frame_index = state.frame_index.wrapping_add(1);
+    state.accumulated_frames = state.accumulated_frames.saturating_add(1);
+}
@@ -0,0 +1,8 @@
+use bevy::prelude::*;
+use bevy::reflect::TypeUuid;
+use bevy::render::pipeline::PipelineDescriptor;
+
+/// Blends the ray pass output with the previous frames.
+/// See [TemporalAntiAliasingSettings](super::settings::TemporalAntiAliasingSettings).
+pub const TAA_PIPELINE_HANDLE: HandleUntyped =
+    HandleUntyped::weak_from_u64(PipelineDescriptor::TYPE_UUID, 0x786f7ab62875ebc0);
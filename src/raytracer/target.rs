@@ -0,0 +1,107 @@
+use std::borrow::Cow;
+use bevy::prelude::*;
+use bevy::render::render_graph::{Node, ResourceSlotInfo, ResourceSlots};
+use bevy::render::renderer::{RenderContext, RenderResourceId, RenderResourceType, TextureId};
+use bevy::render::texture::{Extent3d, TextureDescriptor};
+use bevy::window::WindowId;
+
+/// What a camera rendering the voxels draws into.
+/// See [add_ray_pass_camera](super::add_ray_pass_camera).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RayPassTarget {
+    /// The swap chain of a window
+    Window(WindowId),
+    /// A part `[x, y, width, height]` of the swap chain of a window, in fractions of its size
+    /// from its top left corner, for split-screen
+    Viewport {
+        window: WindowId,
+        viewport: [f32; 4],
+    },
+    /// A texture, for minimaps or security cameras
+    Texture { width: u32, height: u32 },
+}
+
+impl RayPassTarget {
+    pub fn window(&self) -> Option<WindowId> {
+        match *self {
+            RayPassTarget::Window(window) => Some(window),
+            RayPassTarget::Viewport { window, .. } => Some(window),
+            RayPassTarget::Texture { .. } => None,
+        }
+    }
+
+    /// Size in pixels of the textures the voxels are rendered into
+    pub fn size(&self, windows: &Windows) -> Extent3d {
+        let (width, height) = match *self {
+            RayPassTarget::Window(window) => {
+                let window = windows.get(window).expect("Target window doesn't exist");
+                (window.physical_width(), window.physical_height())
+            }
+            RayPassTarget::Viewport { window, viewport } => {
+                let window = windows.get(window).expect("Target window doesn't exist");
+                (
+                    (window.physical_width() as f32 * viewport[2]) as u32,
+                    (window.physical_height() as f32 * viewport[3]) as u32,
+                )
+            }
+            RayPassTarget::Texture { width, height } => (width, height),
+        };
+        // Minimized windows have no size, but textures can't be empty
+        Extent3d {
+            width: width.max(1),
+            height: height.max(1),
+            depth: 1,
+        }
+    }
+}
+
+/// A texture of the size of a [RayPassTarget], recreated when the target is resized
+#[derive(Debug)]
+pub struct TargetTextureNode {
+    target: RayPassTarget,
+    descriptor: TextureDescriptor,
+    texture: Option<TextureId>,
+}
+
+impl TargetTextureNode {
+    pub const OUT_TEXTURE: &'static str = "texture";
+
+    pub fn new(target: RayPassTarget, descriptor: TextureDescriptor) -> Self {
+        TargetTextureNode {
+            target,
+            descriptor,
+            texture: None,
+        }
+    }
+}
+
+impl Node for TargetTextureNode {
+    fn output(&self) -> &[ResourceSlotInfo] {
+        static OUTPUT: &[ResourceSlotInfo] = &[ResourceSlotInfo {
+            name: Cow::Borrowed(TargetTextureNode::OUT_TEXTURE),
+            resource_type: RenderResourceType::Texture,
+        }];
+        OUTPUT
+    }
+
+    fn update(
+        &mut self,
+        _world: &World,
+        resources: &Resources,
+        render_context: &mut dyn RenderContext,
+        _input: &ResourceSlots,
+        output: &mut ResourceSlots,
+    ) {
+        let windows = resources.get::<Windows>().unwrap();
+        let size = self.target.size(&windows);
+        if self.texture.is_some() && self.descriptor.size == size {
+            return;
+        }
+        self.descriptor.size = size;
+        let texture = render_context.resources().create_texture(self.descriptor);
+        if let Some(old_texture) = self.texture.replace(texture) {
+            render_context.resources().remove_texture(old_texture);
+        }
+        output.set(0, RenderResourceId::Texture(texture));
+    }
+}
@@ -0,0 +1,131 @@
+use bevy::core::Bytes;
+use bevy::prelude::*;
+use bevy::reflect::TypeUuid;
+use bevy::render::pipeline::PipelineDescriptor;
+use bevy::render::render_graph::{Node, ResourceSlots};
+use bevy::render::renderer::{
+    BufferId, BufferInfo, BufferUsage, RenderContext, RenderResourceBinding,
+    RenderResourceBindings,
+};
+
+pub const TONEMAPPING_PIPELINE_HANDLE: HandleUntyped =
+    HandleUntyped::weak_from_u64(PipelineDescriptor::TYPE_UUID, 0x786f7ab62875ebbe);
+
+/// Maps the high dynamic range colors of the ray pass to the colors of the screen.
+/// Without it, bright emissive and sunlit surfaces clip.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tonemapping {
+    pub operator: TonemappingOperator,
+    /// Factor applied to the colors before tone mapping
+    pub exposure: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TonemappingOperator {
+    /// Colors above 1 clip
+    None,
+    /// `color / (1 + color)`, compressing highlights smoothly but washing out colors
+    Reinhard,
+    /// Narkowicz's fit of the ACES filmic curve, with more contrast
+    Aces,
+}
+
+impl Default for Tonemapping {
+    fn default() -> Self {
+        Tonemapping {
+            operator: TonemappingOperator::Aces,
+            exposure: 1.0,
+        }
+    }
+}
+
+const TONEMAPPING_SIZE: usize = 8;
+
+impl Bytes for Tonemapping {
+    fn write_bytes(&self, buffer: &mut [u8]) {
+        let operator: u32 = match self.operator {
+            TonemappingOperator::None => 0,
+            TonemappingOperator::Reinhard => 1,
+            TonemappingOperator::Aces => 2,
+        };
+        operator.write_bytes(&mut buffer[0..4]);
+        self.exposure.write_bytes(&mut buffer[4..8]);
+    }
+    fn byte_len(&self) -> usize {
+        TONEMAPPING_SIZE
+    }
+}
+
+/// Uploads the [Tonemapping] resource when it changes, bound as `Tonemapping` for the tone
+/// mapping passes of all the cameras
+#[derive(Debug, Default)]
+pub struct TonemappingNode {
+    buffer: Option<BufferId>,
+    /// The settings in the buffer
+    tonemapping: Option<Tonemapping>,
+}
+
+impl TonemappingNode {
+    pub fn new() -> Self {
+        TonemappingNode {
+            buffer: None,
+            tonemapping: None,
+        }
+    }
+}
+
+impl Node for TonemappingNode {
+    fn update(
+        &mut self,
+        _world: &World,
+        resources: &Resources,
+        render_context: &mut dyn RenderContext,
+        _input: &ResourceSlots,
+        _output: &mut ResourceSlots,
+    ) {
+        let mut render_resource_bindings = resources.get_mut::<RenderResourceBindings>().unwrap();
+        let tonemapping = resources.get::<Tonemapping>().unwrap();
+        if self.tonemapping.as_ref() == Some(&*tonemapping) {
+            return;
+        }
+        let buffer = *self.buffer.get_or_insert_with(|| {
+            let buffer = render_context.resources().create_buffer(BufferInfo {
+                size: TONEMAPPING_SIZE,
+                buffer_usage: BufferUsage::UNIFORM | BufferUsage::COPY_DST,
+                ..Default::default()
+            });
+            render_resource_bindings.set(
+                "Tonemapping",
+                RenderResourceBinding::Buffer {
+                    buffer,
+                    range: 0..TONEMAPPING_SIZE as u64,
+                    dynamic_index: None,
+                },
+            );
+            buffer
+        });
+        // Rarely changes, so the staging buffer isn't kept around
+        let staging_buffer = render_context.resources().create_buffer(BufferInfo {
+            size: TONEMAPPING_SIZE,
+            buffer_usage: BufferUsage::MAP_WRITE | BufferUsage::COPY_SRC,
+            mapped_at_creation: true,
+        });
+        render_context.resources().write_mapped_buffer(
+            staging_buffer,
+            0..TONEMAPPING_SIZE as u64,
+            &mut |data: &mut [u8], _renderer| {
+                tonemapping.write_bytes(data);
+            },
+        );
+        render_context.resources().unmap_buffer(staging_buffer);
+        render_context.copy_buffer_to_buffer(
+            staging_buffer,
+            0,
+            buffer,
+            0,
+            TONEMAPPING_SIZE as u64,
+        );
+        render_context.resources().remove_buffer(staging_buffer);
+        self.tonemapping = Some(tonemapping.clone());
+    }
+}
@@ -0,0 +1,366 @@
+//! CPU implementation of the octree traversal in `ray.frag`.
+//!
+//! It reads the same node layout that `ChunkNode` uploads to the GPU, so it can be used
+//! to validate the shader traversal and to cast rays against chunks on the CPU.
+//! Rays walk the leaves on an integer grid of `GRID_SIZE` cells per axis and always move
+//! into the leaf across the face with the smallest t value, so no epsilons are involved.
+use bevy::prelude::*;
+use std::convert::TryInto;
+
+/// Deepest octree level the traversal stack can hold. Matches `MAX_OCTREE_DEPTH` in `ray.frag`.
+pub const MAX_OCTREE_DEPTH: usize = 16;
+
+/// Size of a single node in the GPU buffer.
+/// ```glsl
+/// struct Node {
+///     uint8_t _padding1;
+///     uint8_t freemask;
+///     uint16_t _padding2;
+///     uint children;
+///     uint16_t data[8];
+/// };
+/// ```
+pub const NODE_SIZE: usize = 24;
+
+/// A view into octree nodes serialized with `Octree::copy_into_slice`.
+#[derive(Copy, Clone)]
+pub struct Nodes<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Nodes<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Nodes { data }
+    }
+    pub fn freemask(&self, node: u32) -> u8 {
+        self.data[node as usize * NODE_SIZE + 1]
+    }
+    pub fn children(&self, node: u32) -> u32 {
+        let offset = node as usize * NODE_SIZE + 4;
+        u32::from_le_bytes(self.data[offset..offset + 4].try_into().unwrap())
+    }
+    pub fn data(&self, node: u32, child: u32) -> u16 {
+        let offset = node as usize * NODE_SIZE + 8 + child as usize * 2;
+        u16::from_le_bytes(self.data[offset..offset + 2].try_into().unwrap())
+    }
+}
+
+/// Number of cells along each axis of the integer grid the traversal runs on.
+/// A leaf at depth `d` covers `GRID_SIZE >> d` cells.
+pub const GRID_SIZE: u32 = 1 << MAX_OCTREE_DEPTH;
+
+#[derive(Debug, Copy, Clone)]
+pub struct RayHit {
+    pub voxel: u16,
+    /// Distance along the ray
+    pub t: f32,
+    pub hitpoint: Vec3,
+    /// Normal of the face the ray entered the voxel through.
+    /// Zero when the ray started inside the voxel.
+    pub normal: Vec3,
+    /// The voxel hit, in world space
+    pub hitbox: Vec4,
+    /// Min corner of the voxel on the integer grid
+    pub cell: [u32; 3],
+    pub cell_size: u32,
+    /// Number of node fetches spent on the ray
+    pub iterations: u32,
+}
+
+pub fn intersect_aabb(origin: Vec3, dir: Vec3, bounding_box: Vec4) -> (f32, f32) {
+    let box_min = bounding_box.truncate();
+    let box_max = box_min + Vec3::splat(bounding_box.w);
+    let t_min = (box_min - origin) / dir;
+    let t_max = (box_max - origin) / dir;
+    let t1 = t_min.min(t_max);
+    let t2 = t_min.max(t_max);
+    (t1.x.max(t1.y).max(t1.z), t2.x.min(t2.y).min(t2.z))
+}
+
+fn sign(x: f32) -> f32 {
+    // Unlike f32::signum, GLSL sign() returns 0 for 0
+    if x > 0.0 {
+        1.0
+    } else if x < 0.0 {
+        -1.0
+    } else {
+        0.0
+    }
+}
+
+fn mask_location_nth_one(mask: u8, location: u32) -> u32 {
+    (mask as u32 & ((1 << location) - 1)).count_ones()
+}
+
+fn child_index(cell: [u32; 3], shift: u32) -> u32 {
+    (((cell[0] >> shift) & 1) << 2) | (((cell[1] >> shift) & 1) << 1) | ((cell[2] >> shift) & 1)
+}
+
+/// Finds the leaf containing `cell` by descending from the node at `depth` of the stack.
+/// Returns the voxel, the leaf min corner and the leaf size.
+fn descend(
+    nodes: Nodes,
+    stack: &mut [u32; MAX_OCTREE_DEPTH],
+    depth: &mut usize,
+    cell: [u32; 3],
+    counter: &mut u32,
+) -> (u16, [u32; 3], u32) {
+    loop {
+        *counter += 1;
+        let node_index = stack[*depth];
+        let shift = (MAX_OCTREE_DEPTH - 1 - *depth) as u32;
+        let child_index = child_index(cell, shift);
+        let freemask = nodes.freemask(node_index);
+        if freemask & (1 << child_index) == 0 || *depth == MAX_OCTREE_DEPTH - 1 {
+            let leaf_size = 1 << shift;
+            let leaf_min = [
+                cell[0] & !(leaf_size - 1),
+                cell[1] & !(leaf_size - 1),
+                cell[2] & !(leaf_size - 1),
+            ];
+            return (nodes.data(node_index, child_index), leaf_min, leaf_size);
+        }
+        *depth += 1;
+        stack[*depth] = nodes.children(node_index) + mask_location_nth_one(freemask, child_index);
+    }
+}
+
+fn grid_cell(position: f32) -> u32 {
+    (position.floor().max(0.0) as u32).min(GRID_SIZE - 1)
+}
+
+/// Finds the voxel at `position` by descending from the root node.
+/// `bounding_box` is shrunk to the leaf containing the position.
+pub fn material_at_position(nodes: Nodes, bounding_box: &mut Vec4, position: Vec3) -> u16 {
+    let scale = GRID_SIZE as f32 / bounding_box.w;
+    let local: [f32; 3] = ((position - bounding_box.truncate()) * scale).into();
+    let cell = [grid_cell(local[0]), grid_cell(local[1]), grid_cell(local[2])];
+    let mut stack = [0; MAX_OCTREE_DEPTH];
+    let (voxel, leaf_min, leaf_size) = descend(nodes, &mut stack, &mut 0, cell, &mut 0);
+    *bounding_box = Vec4::new(
+        bounding_box.x + leaf_min[0] as f32 / scale,
+        bounding_box.y + leaf_min[1] as f32 / scale,
+        bounding_box.z + leaf_min[2] as f32 / scale,
+        leaf_size as f32 / scale,
+    );
+    voxel
+}
+
+/// Walks the leaves of the octree starting from `cell`, which contains `origin + dir * t_start`.
+/// When `restart_from_root` is set, every leaf is located from the root node, which is how
+/// `ray.frag` used to work. This is only useful for validating the stack based traversal.
+#[allow(clippy::too_many_arguments)]
+fn march_cells(
+    nodes: Nodes,
+    initial_box: Vec4,
+    origin: Vec3,
+    dir: Vec3,
+    t_start: f32,
+    mut cell: [i64; 3],
+    mut normal: Vec3,
+    max_iterations: u32,
+    restart_from_root: bool,
+) -> Option<RayHit> {
+    let scale = GRID_SIZE as f32 / initial_box.w;
+    let local_origin: [f32; 3] = ((origin + dir * t_start - initial_box.truncate()) * scale).into();
+    let local_dir: [f32; 3] = (dir * scale).into();
+    let mut t = 0.0_f32;
+
+    let mut stack = [0_u32; MAX_OCTREE_DEPTH];
+    let mut depth = 0;
+    let mut counter = 0;
+    while counter < max_iterations {
+        if cell.iter().any(|&c| c < 0 || c >= GRID_SIZE as i64) {
+            return None;
+        }
+        let current = [cell[0] as u32, cell[1] as u32, cell[2] as u32];
+        if restart_from_root {
+            depth = 0;
+        }
+        let (voxel, leaf_min, leaf_size) =
+            descend(nodes, &mut stack, &mut depth, current, &mut counter);
+        if voxel > 0 {
+            let t = t_start + t;
+            return Some(RayHit {
+                voxel,
+                t,
+                hitpoint: origin + dir * t,
+                normal,
+                hitbox: Vec4::new(
+                    initial_box.x + leaf_min[0] as f32 / scale,
+                    initial_box.y + leaf_min[1] as f32 / scale,
+                    initial_box.z + leaf_min[2] as f32 / scale,
+                    leaf_size as f32 / scale,
+                ),
+                cell: leaf_min,
+                cell_size: leaf_size,
+                iterations: counter,
+            });
+        }
+
+        // Step into the neighbor leaf through the face with the smallest t
+        let mut t_planes = [f32::MAX; 3];
+        for (axis, t_plane) in t_planes.iter_mut().enumerate() {
+            if local_dir[axis] != 0.0 {
+                let plane = if local_dir[axis] > 0.0 {
+                    (leaf_min[axis] + leaf_size) as f32
+                } else {
+                    leaf_min[axis] as f32
+                };
+                *t_plane = (plane - local_origin[axis]) / local_dir[axis];
+            }
+        }
+        let t_exit = t_planes[0].min(t_planes[1]).min(t_planes[2]);
+        // Pick exactly one axis even when the ray leaves through an edge or a corner
+        let exit_axis = t_planes.iter().position(|&t| t == t_exit).unwrap();
+        t = t.max(t_exit);
+
+        let mut next = [0_i64; 3];
+        let mut normal_array = [0.0_f32; 3];
+        for (axis, next) in next.iter_mut().enumerate() {
+            let min = leaf_min[axis] as i64;
+            let max = (leaf_min[axis] + leaf_size) as i64 - 1;
+            if axis == exit_axis {
+                *next = if local_dir[axis] > 0.0 { max + 1 } else { min - 1 };
+                normal_array[axis] = -sign(local_dir[axis]);
+            } else {
+                let position = (local_origin[axis] + local_dir[axis] * t).floor() as i64;
+                *next = position.max(min).min(max);
+            }
+        }
+        normal = normal_array.into();
+
+        // Pop the nodes that do not contain the next cell.
+        // Those are the ones below the highest bit that changed.
+        let changed = (0..3)
+            .map(|axis| (next[axis] as u32) ^ current[axis])
+            .fold(0, |acc, diff| acc | diff);
+        if changed != 0 {
+            let changed_bit = 31 - changed.leading_zeros() as usize;
+            depth = depth.min((MAX_OCTREE_DEPTH - 1).saturating_sub(changed_bit));
+        }
+        cell = next;
+    }
+    None
+}
+
+/// The cell containing `position` on the integer grid. Positions on a cell boundary
+/// belong to the cell the ray is heading into.
+fn starting_cell(initial_box: Vec4, position: Vec3, dir: Vec3) -> [i64; 3] {
+    let scale = GRID_SIZE as f32 / initial_box.w;
+    let local: [f32; 3] = ((position - initial_box.truncate()) * scale).into();
+    let dir: [f32; 3] = dir.into();
+    let mut cell = [0; 3];
+    for (axis, cell) in cell.iter_mut().enumerate() {
+        let floor = local[axis].floor();
+        let mut c = floor as i64;
+        if dir[axis] < 0.0 && floor == local[axis] {
+            c -= 1;
+        }
+        *cell = c.max(0).min(GRID_SIZE as i64 - 1);
+    }
+    cell
+}
+
+fn ray_march_internal(
+    nodes: Nodes,
+    initial_box: Vec4,
+    origin: Vec3,
+    dir: Vec3,
+    max_iterations: u32,
+    restart_from_root: bool,
+) -> Option<RayHit> {
+    let (t_min, t_max) = intersect_aabb(origin, dir, initial_box);
+    if t_min > t_max || t_max < 0.0 {
+        return None;
+    }
+    let t_start = t_min.max(0.0);
+    let cell = starting_cell(initial_box, origin + dir * t_start, dir);
+
+    let mut normal = Vec3::zero();
+    if t_min > 0.0 {
+        let box_min = initial_box.truncate();
+        let box_max = box_min + Vec3::splat(initial_box.w);
+        let t1: [f32; 3] = ((box_min - origin) / dir).min((box_max - origin) / dir).into();
+        let dir_array: [f32; 3] = dir.into();
+        let mut normal_array = [0.0; 3];
+        for (axis, normal) in normal_array.iter_mut().enumerate() {
+            if t1[axis] == t_min {
+                *normal = -sign(dir_array[axis]);
+            }
+        }
+        normal = normal_array.into();
+    }
+    march_cells(
+        nodes,
+        initial_box,
+        origin,
+        dir,
+        t_start,
+        cell,
+        normal,
+        max_iterations,
+        restart_from_root,
+    )
+}
+
+/// Casts a ray through the octree using the stack based traversal of `ray.frag`.
+/// Returns None when the ray leaves the chunk or runs out of iterations.
+pub fn ray_march(
+    nodes: Nodes,
+    initial_box: Vec4,
+    origin: Vec3,
+    dir: Vec3,
+    max_iterations: u32,
+) -> Option<RayHit> {
+    ray_march_internal(nodes, initial_box, origin, dir, max_iterations, false)
+}
+
+/// Same as `ray_march`, but every leaf is located by descending from the root.
+/// Kept as a reference for validating the traversal stack.
+pub fn ray_march_from_root(
+    nodes: Nodes,
+    initial_box: Vec4,
+    origin: Vec3,
+    dir: Vec3,
+    max_iterations: u32,
+) -> Option<RayHit> {
+    ray_march_internal(nodes, initial_box, origin, dir, max_iterations, true)
+}
+
+/// Casts a secondary ray leaving the face of a previous hit.
+/// The ray starts in the cell in front of the face, so it never hits the voxel it starts from.
+pub fn ray_march_from_hit(
+    nodes: Nodes,
+    initial_box: Vec4,
+    from: &RayHit,
+    dir: Vec3,
+    max_iterations: u32,
+) -> Option<RayHit> {
+    let scale = GRID_SIZE as f32 / initial_box.w;
+    let local: [f32; 3] = ((from.hitpoint - initial_box.truncate()) * scale).into();
+    let normal: [f32; 3] = from.normal.into();
+    let mut cell = [0_i64; 3];
+    for (axis, cell) in cell.iter_mut().enumerate() {
+        let min = from.cell[axis] as i64;
+        let max = (from.cell[axis] + from.cell_size) as i64 - 1;
+        *cell = if normal[axis] > 0.0 {
+            max + 1
+        } else if normal[axis] < 0.0 {
+            min - 1
+        } else {
+            (local[axis].floor() as i64).max(min).min(max)
+        };
+    }
+    march_cells(
+        nodes,
+        initial_box,
+        from.hitpoint,
+        dir,
+        0.0,
+        cell,
+        from.normal,
+        max_iterations,
+        false,
+    )
+}
@@ -0,0 +1,54 @@
+use bevy::prelude::*;
+use bevy::render::camera::OrthographicProjection;
+use ray_tracing::material::{ColoredMaterial, MaterialPalette, DEFAULT_MATERIAL_PALETTE_HANDLE};
+use ray_tracing::raytracer::chunk::{Chunk, ChunkBundle};
+use ray_tracing::render_to_image;
+use ray_tracing::Voxel;
+use svo::octree::Octree;
+
+const SIZE: u32 = 64;
+
+/// A red block filling its chunk, around the origin
+fn red_block(
+    commands: &mut Commands,
+    mut chunks: ResMut<Assets<Chunk>>,
+    mut material_palettes: ResMut<Assets<MaterialPalette>>,
+) {
+    let palette = material_palettes
+        .get_mut(DEFAULT_MATERIAL_PALETTE_HANDLE)
+        .unwrap();
+    let mut colored_material = ColoredMaterial::default();
+    colored_material.color_palette[0] = Color::rgb(1.0, 0.0, 0.0);
+    let voxel = palette.add_colored_material(colored_material);
+
+    let mut octree: Octree<Voxel> = Octree::new();
+    for x in 0..16u32 {
+        for y in 0..16u32 {
+            for z in 0..16u32 {
+                octree.set(x, y, z, 16, voxel);
+            }
+        }
+    }
+    let chunk = Chunk::new(octree, Vec4::new(-8.0, -8.0, -8.0, 16.0));
+    commands.spawn(ChunkBundle::new(chunks.add(chunk)));
+}
+
+/// Without any texture in the scene, so it relies on the texture repo of the plugin
+#[test]
+fn chunks_are_drawn_without_textures() {
+    let camera = OrthographicCameraBundle {
+        transform: Transform::from_translation(Vec3::new(40.0, 40.0, 40.0))
+            .looking_at(Vec3::default(), Vec3::unit_y()),
+        orthographic_projection: OrthographicProjection {
+            scale: 0.05,
+            ..OrthographicCameraBundle::new_3d().orthographic_projection
+        },
+        ..OrthographicCameraBundle::new_3d()
+    };
+    let image = render_to_image(red_block.system(), camera, (SIZE, SIZE)).unwrap();
+    assert_eq!(image.dimensions(), (SIZE, SIZE));
+    // The block covers the center of the image, on a transparent background
+    let center = image.get_pixel(SIZE / 2, SIZE / 2);
+    assert!(center[3] > 0, "Nothing drawn at the center: {:?}", center);
+    assert!(center[0] > center[1] && center[0] > center[2], "Not red: {:?}", center);
+}
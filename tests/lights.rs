@@ -0,0 +1,82 @@
+use bevy::prelude::*;
+use ray_tracing::lights::node::LightData;
+use ray_tracing::lights::{AreaLight, PointLight, SpotLight};
+
+/// A chunk of size 64 at the origin
+fn chunk() -> Vec4 {
+    Vec4::new(0.0, 0.0, 0.0, 64.0)
+}
+
+fn at(x: f32, y: f32, z: f32) -> GlobalTransform {
+    GlobalTransform::from_translation(Vec3::new(x, y, z))
+}
+
+#[test]
+fn point_light_inside_chunk_reaches_it() {
+    let light = PointLight {
+        range: 1.0,
+        ..Default::default()
+    };
+    assert!(LightData::point(&light, &at(32.0, 32.0, 32.0)).reaches(chunk()));
+}
+
+#[test]
+fn point_light_reaches_chunk_within_range() {
+    let light = PointLight {
+        range: 16.0,
+        ..Default::default()
+    };
+    // 15 away from the closest face
+    assert!(LightData::point(&light, &at(-15.0, 32.0, 32.0)).reaches(chunk()));
+    // 17 away from the closest face
+    assert!(!LightData::point(&light, &at(-17.0, 32.0, 32.0)).reaches(chunk()));
+    assert!(!LightData::point(&light, &at(32.0, 81.0, 32.0)).reaches(chunk()));
+}
+
+#[test]
+fn point_light_range_is_measured_to_the_closest_corner() {
+    let light = PointLight {
+        range: 16.0,
+        ..Default::default()
+    };
+    // 12 away from the corner along each axis, about 20.8 in total
+    assert!(!LightData::point(&light, &at(-12.0, -12.0, -12.0)).reaches(chunk()));
+    // About 13.9 away from the corner
+    assert!(LightData::point(&light, &at(-8.0, -8.0, -8.0)).reaches(chunk()));
+}
+
+#[test]
+fn spot_light_range_ignores_direction() {
+    let light = SpotLight {
+        range: 16.0,
+        ..Default::default()
+    };
+    // Pointing away from the chunk
+    let transform =
+        at(-10.0, 32.0, 32.0).looking_at(Vec3::new(-20.0, 32.0, 32.0), Vec3::unit_y());
+    assert!(LightData::spot(&light, &transform).reaches(chunk()));
+}
+
+#[test]
+fn area_light_range_extends_by_its_half_diagonal() {
+    let light = AreaLight {
+        range: 16.0,
+        width: 6.0,
+        height: 8.0,
+        ..Default::default()
+    };
+    // The half diagonal is 5, so the light reaches up to 21 away from its center
+    assert!(LightData::area(&light, &at(-20.0, 32.0, 32.0)).reaches(chunk()));
+    assert!(!LightData::area(&light, &at(-22.0, 32.0, 32.0)).reaches(chunk()));
+}
+
+#[test]
+fn reach_accounts_for_chunk_origin() {
+    let light = PointLight {
+        range: 16.0,
+        ..Default::default()
+    };
+    let next_chunk = Vec4::new(64.0, 0.0, 0.0, 64.0);
+    assert!(!LightData::point(&light, &at(32.0, 32.0, 32.0)).reaches(next_chunk));
+    assert!(LightData::point(&light, &at(56.0, 32.0, 32.0)).reaches(next_chunk));
+}
@@ -0,0 +1,126 @@
+use ray_tracing::material::{Material, MaterialPalette};
+use ray_tracing::{Axis, Face, Orientation, Voxel};
+
+const FACES: [Face; 6] = [
+    Face::East,
+    Face::West,
+    Face::Top,
+    Face::Bottom,
+    Face::South,
+    Face::North,
+];
+
+fn orientations() -> impl Iterator<Item = Orientation> {
+    (0..Orientation::COUNT).map(Orientation::from_bits)
+}
+
+/// The faces every face of the voxel turns into
+fn rotated_faces(orientation: Orientation) -> Vec<Face> {
+    FACES.iter().map(|&face| orientation.rotate(face)).collect()
+}
+
+#[test]
+fn orientations_are_distinct_rotations() {
+    let rotations: Vec<Vec<Face>> = orientations().map(rotated_faces).collect();
+    for (i, faces) in rotations.iter().enumerate() {
+        // Each face turns into a different face
+        for face in FACES.iter() {
+            assert_eq!(faces.iter().filter(|rotated| *rotated == face).count(), 1);
+        }
+        // Opposite faces stay opposite
+        for pair in faces.chunks(2) {
+            let normal = pair[0].normal();
+            let opposite = pair[1].normal();
+            assert_eq!(normal, [-opposite[0], -opposite[1], -opposite[2]]);
+        }
+        assert!(
+            rotations[..i].iter().all(|other| other != faces),
+            "Orientation {} repeats a rotation",
+            i
+        );
+    }
+}
+
+#[test]
+fn orientations_are_proper_rotations() {
+    // Mirror images would turn East, Top and South into a left handed basis
+    for orientation in orientations() {
+        let x = orientation.rotate(Face::East).normal();
+        let y = orientation.rotate(Face::Top).normal();
+        let z = orientation.rotate(Face::South).normal();
+        let cross = [
+            x[1] * y[2] - x[2] * y[1],
+            x[2] * y[0] - x[0] * y[2],
+            x[0] * y[1] - x[1] * y[0],
+        ];
+        assert_eq!(cross, z, "{:?} is a reflection", orientation);
+    }
+}
+
+#[test]
+fn default_orientation_is_unrotated() {
+    assert_eq!(Orientation::default().bits(), 0);
+    assert_eq!(rotated_faces(Orientation::default()), FACES.to_vec());
+}
+
+#[test]
+fn new_turns_top_face_up() {
+    for &up in FACES.iter() {
+        for turns in 0..4 {
+            assert_eq!(Orientation::new(up, turns).rotate(Face::Top), up);
+        }
+    }
+}
+
+#[test]
+fn facing_turns_north_face() {
+    for &face in FACES.iter() {
+        assert_eq!(Orientation::facing(face).rotate(Face::North), face);
+    }
+}
+
+#[test]
+fn facing_keeps_top_face_up_for_horizontal_faces() {
+    for &face in &[Face::East, Face::West, Face::South, Face::North] {
+        assert_eq!(Orientation::facing(face).rotate(Face::Top), Face::Top);
+    }
+}
+
+#[test]
+fn from_axis_turns_top_face_along_axis() {
+    assert_eq!(Orientation::from_axis(Axis::X).rotate(Face::Top), Face::East);
+    assert_eq!(Orientation::from_axis(Axis::Y).rotate(Face::Top), Face::Top);
+    assert_eq!(Orientation::from_axis(Axis::Z).rotate(Face::Top), Face::South);
+}
+
+#[test]
+fn bits_round_trip() {
+    for bits in 0..Orientation::COUNT {
+        assert_eq!(Orientation::from_bits(bits).bits(), bits);
+    }
+    for orientation in orientations() {
+        let voxel = Voxel::new(1023).with_orientation(orientation);
+        assert_eq!(voxel.orientation(), orientation);
+    }
+}
+
+#[test]
+#[should_panic]
+fn from_bits_rejects_invalid_orientations() {
+    Orientation::from_bits(Orientation::COUNT);
+}
+
+#[test]
+#[should_panic]
+fn voxel_rejects_ids_above_1023() {
+    Voxel::new(1024);
+}
+
+#[test]
+#[should_panic]
+fn palette_rejects_materials_above_1023() {
+    let mut palette = MaterialPalette::new();
+    for _ in 0..1024 {
+        palette.add_material(Material::default());
+    }
+}
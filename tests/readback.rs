@@ -0,0 +1,15 @@
+use ray_tracing::raytracer::readback_node::padded_bytes_per_row;
+
+#[test]
+fn rows_are_padded_to_256_bytes() {
+    assert_eq!(padded_bytes_per_row(1), 256);
+    assert_eq!(padded_bytes_per_row(63), 256);
+    assert_eq!(padded_bytes_per_row(65), 512);
+    assert_eq!(padded_bytes_per_row(100), 512);
+}
+
+#[test]
+fn aligned_rows_are_not_padded() {
+    assert_eq!(padded_bytes_per_row(64), 256);
+    assert_eq!(padded_bytes_per_row(1920), 1920 * 4);
+}
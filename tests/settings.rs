@@ -0,0 +1,141 @@
+use bevy::core::Bytes;
+use bevy::render::color::Color;
+use ray_tracing::raytracer::fog::{Fog, FogMode};
+use ray_tracing::raytracer::settings::{
+    AmbientOcclusionSettings, GlobalIlluminationSettings, RayTracerSettings, ReflectionSettings,
+    ShadowSettings, TemporalAntiAliasingSettings, ACCUMULATED_FRAMES_OFFSET, FOG_OFFSET,
+    FRAME_INDEX_OFFSET, ITERATION_HEATMAP_OFFSET, PREVIOUS_VIEW_PROJ_OFFSET,
+    RAY_TRACER_SETTINGS_SIZE, TAA_SETTINGS_OFFSET, TIME_OFFSET,
+};
+
+fn u32_at(buffer: &[u8], offset: usize) -> u32 {
+    u32::from_ne_bytes([buffer[offset], buffer[offset + 1], buffer[offset + 2], buffer[offset + 3]])
+}
+
+fn f32_at(buffer: &[u8], offset: usize) -> f32 {
+    f32::from_bits(u32_at(buffer, offset))
+}
+
+/// Every member of the `RayTracerSettings` block of ray.frag, at its std140 offset
+#[test]
+fn settings_are_written_where_the_shader_reads_them() {
+    let settings = RayTracerSettings {
+        sun_shadows: ShadowSettings {
+            enabled: true,
+            max_iterations: 1,
+            min_grazing_angle: 0.5,
+        },
+        light_shadows: ShadowSettings {
+            enabled: false,
+            max_iterations: 2,
+            min_grazing_angle: 0.25,
+        },
+        area_light_samples: 3,
+        ambient_occlusion: AmbientOcclusionSettings {
+            enabled: true,
+            samples: 4,
+            max_iterations: 5,
+            distance: 6.0,
+        },
+        global_illumination: GlobalIlluminationSettings {
+            enabled: true,
+            max_iterations: 7,
+            max_accumulated_frames: 8,
+        },
+        reflections: ReflectionSettings {
+            max_bounces: 9,
+            max_iterations: 10,
+        },
+        temporal_anti_aliasing: TemporalAntiAliasingSettings {
+            enabled: true,
+            history_weight: 0.75,
+        },
+        iteration_heatmap: true,
+    };
+    // Filled with a pattern that none of the values above is written as
+    let mut buffer = vec![0xAB; RAY_TRACER_SETTINGS_SIZE];
+    settings.write_bytes(&mut buffer);
+
+    assert_eq!(u32_at(&buffer, 0), 1); // SunShadowsEnabled
+    assert_eq!(u32_at(&buffer, 4), 1); // SunShadowMaxIterations
+    assert_eq!(f32_at(&buffer, 8), 0.5f32.sin()); // SunMinGrazingFactor
+    assert_eq!(u32_at(&buffer, 12), 0); // LightShadowsEnabled
+    assert_eq!(u32_at(&buffer, 16), 2); // LightShadowMaxIterations
+    assert_eq!(f32_at(&buffer, 20), 0.25f32.sin()); // LightMinGrazingFactor
+    assert_eq!(u32_at(&buffer, 24), 3); // AreaLightSamples
+    assert_eq!(u32_at(&buffer, 32), 1); // AmbientOcclusionEnabled
+    assert_eq!(u32_at(&buffer, 36), 4); // AmbientOcclusionSamples
+    assert_eq!(u32_at(&buffer, 40), 5); // AmbientOcclusionMaxIterations
+    assert_eq!(f32_at(&buffer, 44), 6.0); // AmbientOcclusionDistance
+    assert_eq!(u32_at(&buffer, 48), 1); // GIEnabled
+    assert_eq!(u32_at(&buffer, 52), 7); // GIMaxIterations
+    assert_eq!(u32_at(&buffer, 56), 8); // GIMaxAccumulatedFrames
+    assert_eq!(u32_at(&buffer, 64), 9); // ReflectionMaxBounces
+    assert_eq!(u32_at(&buffer, 68), 10); // ReflectionMaxIterations
+    assert_eq!(u32_at(&buffer, TAA_SETTINGS_OFFSET), 1); // TaaEnabled
+    assert_eq!(f32_at(&buffer, TAA_SETTINGS_OFFSET + 4), 0.75); // TaaHistoryWeight
+    assert_eq!(u32_at(&buffer, ITERATION_HEATMAP_OFFSET), 1); // IterationHeatmap
+
+    // Left to the settings node
+    assert_eq!(u32_at(&buffer, FRAME_INDEX_OFFSET), 0xABABABAB);
+    assert_eq!(u32_at(&buffer, ACCUMULATED_FRAMES_OFFSET), 0xABABABAB);
+    assert_eq!(u32_at(&buffer, TIME_OFFSET), 0xABABABAB);
+    let node_bytes = FOG_OFFSET..TAA_SETTINGS_OFFSET;
+    assert!(buffer[node_bytes].iter().all(|&byte| byte == 0xAB));
+}
+
+/// The members of the block the settings node writes, at their std140 offsets
+#[test]
+fn fog_is_written_where_the_shader_reads_it() {
+    let fog = Fog {
+        mode: FogMode::Height {
+            density: 0.5,
+            falloff: 0.25,
+            height: 8.0,
+        },
+        color: Color::rgba(0.0, 1.0, 0.0, 0.5),
+    };
+    let mut buffer = vec![0; RAY_TRACER_SETTINGS_SIZE];
+    fog.write_bytes(&mut buffer[FOG_OFFSET..FOG_OFFSET + fog.byte_len()]);
+
+    // The time is followed by a reserved float, aligning the fog color to 16 bytes
+    assert_eq!(TIME_OFFSET, 72);
+    assert_eq!(FOG_OFFSET, 80);
+    assert_eq!(f32_at(&buffer, 80), 0.0); // FogColor.r
+    assert_eq!(f32_at(&buffer, 84), 1.0); // FogColor.g
+    assert_eq!(f32_at(&buffer, 92), 0.5); // FogColor.a
+    assert_eq!(u32_at(&buffer, 96), 4); // FogMode
+    assert_eq!(f32_at(&buffer, 100), 0.5); // FogParam0
+    assert_eq!(f32_at(&buffer, 104), 0.25); // FogParam1
+    assert_eq!(f32_at(&buffer, 108), 8.0); // FogParam2
+    assert_eq!(PREVIOUS_VIEW_PROJ_OFFSET, 112);
+    assert_eq!(TAA_SETTINGS_OFFSET, PREVIOUS_VIEW_PROJ_OFFSET + 64);
+}
+
+#[test]
+fn iteration_heatmap_ends_the_uniform() {
+    let taa_end = TAA_SETTINGS_OFFSET + TemporalAntiAliasingSettings::default().byte_len();
+    assert_eq!(taa_end, ITERATION_HEATMAP_OFFSET);
+    let heatmap_end = ITERATION_HEATMAP_OFFSET + 4;
+    assert!(heatmap_end <= RAY_TRACER_SETTINGS_SIZE);
+    // Padded to the next multiple of 16 bytes, and not further
+    assert_eq!(RAY_TRACER_SETTINGS_SIZE % 16, 0);
+    assert!(RAY_TRACER_SETTINGS_SIZE - heatmap_end < 16);
+    assert_eq!(RayTracerSettings::default().byte_len(), RAY_TRACER_SETTINGS_SIZE);
+}
+
+#[test]
+fn taa_settings_are_written_at_their_offset() {
+    let mut settings = RayTracerSettings::default();
+    settings.temporal_anti_aliasing = TemporalAntiAliasingSettings {
+        enabled: true,
+        history_weight: 0.5,
+    };
+    let mut buffer = vec![0; RAY_TRACER_SETTINGS_SIZE];
+    settings.write_bytes(&mut buffer);
+    assert_eq!(&buffer[TAA_SETTINGS_OFFSET..TAA_SETTINGS_OFFSET + 4], &1u32.to_ne_bytes());
+    assert_eq!(
+        &buffer[TAA_SETTINGS_OFFSET + 4..TAA_SETTINGS_OFFSET + 8],
+        &0.5f32.to_ne_bytes()
+    );
+}
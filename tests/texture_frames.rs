@@ -0,0 +1,110 @@
+use image::{DynamicImage, Rgba, RgbaImage};
+use ray_tracing::material::texture_repo::{AnimationFrame, TextureFrame, TextureRepo};
+
+const SIZE: u32 = 4;
+
+/// A vertical strip of `count` frames, each filled with its index
+fn strip(count: u32) -> DynamicImage {
+    DynamicImage::ImageRgba8(RgbaImage::from_fn(SIZE, SIZE * count, |_, y| {
+        Rgba([(y / SIZE) as u8, 0, 0, 255])
+    }))
+}
+
+fn frame(index: u32, duration: f32) -> AnimationFrame {
+    AnimationFrame { index, duration }
+}
+
+#[test]
+fn still_textures_have_a_single_frame() {
+    let mut repo = TextureRepo::new(SIZE, SIZE);
+    let handle = repo.insert_animated(strip(1), &[frame(0, 1.0)]);
+    assert_eq!(handle.get(), 1);
+    assert_eq!(repo.len(), 1);
+    assert_eq!(repo.frames()[0].frame_count, 1);
+}
+
+#[test]
+fn only_first_layer_holds_frame_count() {
+    let mut repo = TextureRepo::new(SIZE, SIZE);
+    let handle = repo.insert_animated(strip(3), &[frame(0, 1.0), frame(1, 1.0), frame(2, 1.0)]);
+    assert_eq!(handle.get(), 1);
+    assert_eq!(repo.len(), 3);
+    let counts: Vec<u32> = repo.frames().iter().map(|frame| frame.frame_count).collect();
+    assert_eq!(counts, vec![3, 1, 1]);
+    for frame in repo.frames() {
+        assert_eq!(frame.period, 3.0);
+    }
+}
+
+#[test]
+fn frame_ends_are_cumulative() {
+    let mut repo = TextureRepo::new(SIZE, SIZE);
+    repo.insert_animated(strip(3), &[frame(0, 0.5), frame(1, 1.0), frame(2, 0.25)]);
+    let ends: Vec<f32> = repo.frames().iter().map(|frame| frame.frame_end).collect();
+    assert_eq!(ends, vec![0.5, 1.5, 1.75]);
+    assert_eq!(repo.frames()[0].period, 1.75);
+}
+
+#[test]
+fn repeated_frames_get_their_own_layers() {
+    let mut repo = TextureRepo::new(SIZE, SIZE);
+    let frames = [frame(0, 1.0), frame(1, 1.0), frame(0, 2.0), frame(1, 1.0)];
+    repo.insert_animated(strip(2), &frames);
+    assert_eq!(repo.len(), 4);
+    assert_eq!(
+        repo.frames(),
+        &[
+            TextureFrame {
+                frame_count: 4,
+                period: 5.0,
+                frame_end: 1.0,
+            },
+            TextureFrame {
+                frame_count: 1,
+                period: 5.0,
+                frame_end: 2.0,
+            },
+            TextureFrame {
+                frame_count: 1,
+                period: 5.0,
+                frame_end: 4.0,
+            },
+            TextureFrame {
+                frame_count: 1,
+                period: 5.0,
+                frame_end: 5.0,
+            },
+        ]
+    );
+    // Each layer holds the image of its frame
+    let mut layers: Vec<(u16, u8)> = repo
+        .drain()
+        .map(|(handle, image)| (handle.get(), image.to_rgba8().get_pixel(0, 0).0[0]))
+        .collect();
+    layers.sort();
+    assert_eq!(layers, vec![(1, 0), (2, 1), (3, 0), (4, 1)]);
+}
+
+#[test]
+fn animations_follow_previous_textures() {
+    let mut repo = TextureRepo::new(SIZE, SIZE);
+    repo.insert_animated(strip(1), &[frame(0, 1.0)]);
+    let handle = repo.insert_animated(strip(2), &[frame(0, 1.0), frame(1, 1.0)]);
+    assert_eq!(handle.get(), 2);
+    let counts: Vec<u32> = repo.frames().iter().map(|frame| frame.frame_count).collect();
+    assert_eq!(counts, vec![1, 2, 1]);
+}
+
+#[test]
+#[should_panic]
+fn missing_frames_are_rejected() {
+    let mut repo = TextureRepo::new(SIZE, SIZE);
+    repo.insert_animated(strip(2), &[frame(0, 1.0), frame(2, 1.0)]);
+}
+
+#[test]
+#[should_panic]
+fn zero_periods_are_rejected() {
+    let mut repo = TextureRepo::new(SIZE, SIZE);
+    repo.insert_animated(strip(2), &[frame(0, 0.0), frame(1, 0.0)]);
+}
@@ -0,0 +1,209 @@
+use bevy::prelude::*;
+use ray_tracing::raytracer::chunk::Chunk;
+use ray_tracing::raytracer::traversal::{
+    intersect_aabb, ray_march, ray_march_from_hit, ray_march_from_root, Nodes,
+};
+use ray_tracing::Voxel;
+use svo::octree::Octree;
+
+const MAX_ITERATIONS: u32 = 100000;
+
+/// Small deterministic generator so that failures are reproducible
+struct Lcg(u64);
+
+impl Lcg {
+    fn next_u32(&mut self) -> u32 {
+        self.0 = self
+            .0
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (self.0 >> 33) as u32
+    }
+    fn next_f32(&mut self) -> f32 {
+        self.next_u32() as f32 / (1_u32 << 31) as f32
+    }
+}
+
+fn scattered_chunk(rng: &mut Lcg) -> Chunk {
+    let mut octree: Octree<Voxel> = Octree::new();
+    for _ in 0..400 {
+        let x = rng.next_u32() % 64;
+        let y = rng.next_u32() % 64;
+        let z = rng.next_u32() % 64;
+        octree.set(x, y, z, 64, Voxel::new(1 + rng.next_u32() as u16 % 4));
+    }
+    // A solid floor so that some subtrees collapse into larger leaves
+    for x in 0..64 {
+        for z in 0..64 {
+            octree.set(x, 0, z, 64, Voxel::new(5));
+        }
+    }
+    Chunk::new(octree, Vec4::new(0.0, 0.0, 0.0, 16.0))
+}
+
+#[test]
+fn stack_traversal_matches_restart_from_root() {
+    let mut rng = Lcg(0x5eed);
+    let chunk = scattered_chunk(&mut rng);
+    let data = chunk.node_data();
+    let nodes = Nodes::new(&data);
+
+    let mut stack_iterations = 0;
+    let mut root_iterations = 0;
+    for _ in 0..2000 {
+        let origin = Vec3::new(
+            rng.next_f32() * 48.0 - 16.0,
+            rng.next_f32() * 48.0 - 16.0,
+            rng.next_f32() * 48.0 - 16.0,
+        );
+        let target = Vec3::new(
+            rng.next_f32() * 16.0,
+            rng.next_f32() * 16.0,
+            rng.next_f32() * 16.0,
+        );
+        let dir = (target - origin).normalize();
+        let stack_hit = ray_march(nodes, chunk.bounding_box, origin, dir, MAX_ITERATIONS);
+        let root_hit = ray_march_from_root(nodes, chunk.bounding_box, origin, dir, MAX_ITERATIONS);
+        match (stack_hit, root_hit) {
+            (Some(stack_hit), Some(root_hit)) => {
+                assert_eq!(stack_hit.voxel, root_hit.voxel);
+                assert_eq!(stack_hit.hitbox, root_hit.hitbox);
+                assert_eq!(stack_hit.hitpoint, root_hit.hitpoint);
+                stack_iterations += stack_hit.iterations;
+                root_iterations += root_hit.iterations;
+            }
+            (None, None) => (),
+            (stack_hit, root_hit) => panic!(
+                "Traversals disagree for ray {:?} {:?}: {:?} {:?}",
+                origin, dir, stack_hit, root_hit
+            ),
+        }
+    }
+    assert!(stack_iterations < root_iterations);
+}
+
+const WALL: u16 = 7;
+const WALL_POSITION: u32 = 200;
+
+/// A 512^3 chunk with a one voxel thick wall perpendicular to the given axis
+fn thin_wall_chunk(axis: usize) -> Chunk {
+    let mut octree: Octree<Voxel> = Octree::new();
+    for a in 0..512 {
+        for b in 0..512 {
+            let (x, y, z) = match axis {
+                0 => (WALL_POSITION, a, b),
+                1 => (a, WALL_POSITION, b),
+                _ => (a, b, WALL_POSITION),
+            };
+            octree.set(x, y, z, 512, Voxel::new(WALL));
+        }
+    }
+    Chunk::new(octree, Vec4::new(0.0, 0.0, 0.0, 512.0))
+}
+
+fn component(v: Vec3, axis: usize) -> f32 {
+    let v: [f32; 3] = v.into();
+    v[axis]
+}
+
+/// Shoots a ray and checks that it does not pass through the wall
+fn assert_no_leak(nodes: Nodes, chunk: &Chunk, axis: usize, origin: Vec3, dir: Vec3) {
+    match ray_march(nodes, chunk.bounding_box, origin, dir, MAX_ITERATIONS) {
+        Some(hit) => {
+            assert_eq!(hit.voxel, WALL);
+            assert_eq!(hit.cell_size, 1 << 7);
+            assert_eq!(hit.cell[axis], WALL_POSITION << 7);
+        }
+        None => {
+            // The ray may only leave the chunk if it never reaches the wall
+            let (_, t_exit) = intersect_aabb(origin, dir, chunk.bounding_box);
+            let side = component(origin, axis) - WALL_POSITION as f32;
+            let exit_side = component(origin + dir * t_exit, axis) - WALL_POSITION as f32;
+            assert!(
+                side.signum() == exit_side.signum(),
+                "Ray {:?} {:?} passed through the wall",
+                origin,
+                dir
+            );
+        }
+    }
+}
+
+#[test]
+fn axis_aligned_rays_hit_thin_walls() {
+    for axis in 0..3 {
+        let chunk = thin_wall_chunk(axis);
+        let data = chunk.node_data();
+        let nodes = Nodes::new(&data);
+        let mut normal = [0.0; 3];
+        normal[axis] = 1.0;
+        let normal: Vec3 = normal.into();
+        // Rays along the cell edges are the ones most likely to slip between voxels
+        for &(a, b) in &[(0.5, 0.5), (17.0, 33.0), (256.0, 256.0), (511.0, 0.0), (0.0, 0.0)] {
+            let mut before = [a, b, a];
+            before[axis] = 150.0;
+            let mut after = [b, a, b];
+            after[axis] = 250.0;
+            let mut outside = [a, b, b];
+            outside[axis] = -1000.0;
+            for &(origin, dir) in &[(before, normal), (after, -normal), (outside, normal)] {
+                let origin: Vec3 = origin.into();
+                let hit = ray_march(nodes, chunk.bounding_box, origin, dir, MAX_ITERATIONS)
+                    .unwrap_or_else(|| panic!("Ray {:?} {:?} passed the wall", origin, dir));
+                assert_eq!(hit.voxel, WALL);
+                assert_eq!(hit.normal, -dir);
+            }
+        }
+    }
+}
+
+#[test]
+fn grazing_rays_hit_thin_walls() {
+    let mut rng = Lcg(0xdecade);
+    for axis in 0..3 {
+        let chunk = thin_wall_chunk(axis);
+        let data = chunk.node_data();
+        let nodes = Nodes::new(&data);
+        for _ in 0..5000 {
+            let mut origin = [
+                rng.next_f32() * 512.0,
+                rng.next_f32() * 512.0,
+                rng.next_f32() * 512.0,
+            ];
+            let mut dir = [
+                rng.next_f32() * 2.0 - 1.0,
+                rng.next_f32() * 2.0 - 1.0,
+                rng.next_f32() * 2.0 - 1.0,
+            ];
+            // Close to the wall, nearly parallel to it
+            let side = if rng.next_u32() % 2 == 0 { 1.0 } else { -1.0 };
+            origin[axis] = WALL_POSITION as f32 + 0.5 - side * (0.5 + rng.next_f32() * 4.0);
+            dir[axis] = side * (0.00001 + rng.next_f32() * 0.01);
+            let origin: Vec3 = origin.into();
+            let dir: Vec3 = Vec3::from(dir).normalize();
+            assert_no_leak(nodes, &chunk, axis, origin, dir);
+        }
+    }
+}
+
+#[test]
+fn secondary_rays_do_not_hit_their_origin() {
+    let chunk = thin_wall_chunk(0);
+    let data = chunk.node_data();
+    let nodes = Nodes::new(&data);
+    let mut rng = Lcg(0xfeed);
+    for _ in 0..1000 {
+        let origin = Vec3::new(100.0, rng.next_f32() * 512.0, rng.next_f32() * 512.0);
+        let hit = ray_march(nodes, chunk.bounding_box, origin, Vec3::unit_x(), MAX_ITERATIONS)
+            .unwrap();
+        assert_eq!(hit.normal, -Vec3::unit_x());
+        // Leaving the wall at a grazing angle
+        let dir = Vec3::new(-0.001, rng.next_f32() - 0.5, rng.next_f32() - 0.5).normalize();
+        let leaving = ray_march_from_hit(nodes, chunk.bounding_box, &hit, dir, MAX_ITERATIONS);
+        assert!(leaving.is_none(), "Ray {:?} {:?} hit its origin", hit.hitpoint, dir);
+        // Going back into the wall at a grazing angle
+        let dir = Vec3::new(0.001, rng.next_f32() - 0.5, rng.next_f32() - 0.5).normalize();
+        let entering = ray_march_from_hit(nodes, chunk.bounding_box, &hit, dir, MAX_ITERATIONS);
+        assert_eq!(entering.unwrap().voxel, WALL);
+    }
+}